@@ -9,65 +9,171 @@ use {
             Borrow,
             Cow,
         },
+        collections::HashSet,
         fmt,
-        mem,
-        sync::Arc,
+        marker::PhantomData,
+        str::FromStr,
+        sync::{
+            Arc,
+            Weak,
+        },
+        time::{
+            Duration,
+            Instant,
+        },
     },
     async_trait::async_trait,
-    chrono::prelude::*,
-    futures::TryFutureExt as _,
-    itertools::{
-        EitherOrBoth,
-        Itertools as _,
+    chrono::{
+        TimeDelta,
+        prelude::*,
     },
+    futures::TryFutureExt as _,
+    itertools::Itertools as _,
     reqwest::{
         IntoUrl,
+        Method,
+        RequestBuilder,
         StatusCode,
+        header::{
+            HeaderMap,
+            HeaderValue,
+        },
     },
     serde::{
         Deserialize,
+        Serialize,
         de::DeserializeOwned,
     },
     thiserror::Error,
     tokio::{
-        sync::RwLock,
-        time::sleep,
+        sync::{
+            Mutex,
+            RwLock,
+        },
+        task::JoinHandle,
+        time::{
+            MissedTickBehavior,
+            interval,
+            sleep,
+        },
+    },
+    url::Url,
+    crate::token_store::{
+        StoredToken,
+        TokenStore,
     },
 };
 
 pub mod model;
+pub mod oauth;
+pub mod oidc;
 pub mod paginated;
+pub mod token_store;
 
-pub(crate) const HELIX_BASE_URL: &str = "https://api.twitch.tv/helix";
+const HELIX_BASE_URL: &str = "https://api.twitch.tv/helix";
+const KRAKEN_BASE_URL: &str = "https://api.twitch.tv/v5";
+const OAUTH_BASE_URL: &str = "https://id.twitch.tv/oauth2";
+/// Tokens which expire sooner than this are refreshed before they're used.
+const TOKEN_REFRESH_MARGIN: TimeDelta = TimeDelta::minutes(5);
 
 /// An enum that contains all the different kinds of errors that can occur in the library.
 #[derive(Debug, Error)]
 #[allow(missing_docs)]
 pub enum Error {
+    /// The Twitch API returned an error response with a body in the [documented format](https://dev.twitch.tv/docs/api/guide#errors).
+    #[error("Twitch API returned {status}: {message}")]
+    Api {
+        status: StatusCode,
+        /// A short description of the status code, e.g. `Bad Request`. Empty for some errors returned by the OAuth endpoints.
+        error: String,
+        /// A human-readable description of what went wrong.
+        message: String,
+    },
+    /// The user did not complete the [device code flow](oauth::DeviceCodeFlow) before the code expired.
+    #[error("device code expired before the user authorized the app")]
+    DeviceCodeExpired,
     #[error("tried to get exactly one item from an iterator but it {}", if *.0 { "was empty" } else { "contained multiple items" })]
     ExactlyOne(bool),
     #[error("{0}{}", if let Ok(body) = .1 { format!(", body:\n\n{}", body) } else { String::default() })]
     HttpStatus(#[source] reqwest::Error, reqwest::Result<String>),
+    /// An [ID token](oidc) could not be verified.
+    #[error(transparent)] IdToken(#[from] oidc::IdTokenError),
     #[error(transparent)] InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
+    /// The OAuth token lacks a scope required by the endpoint. Detected using [token validation](Client::validate_token) before sending the request.
+    #[error("the OAuth token is missing the {0} scope")]
+    MissingScope(oauth::Scope),
+    /// The user did not authorize the app during an [OAuth flow](oauth), or the redirect didn't include an authorization code.
+    #[error("OAuth authorization failed: {error}: {description}")]
+    OAuthDenied {
+        error: String,
+        description: String,
+    },
+    /// The `state` parameter returned during an [OAuth flow](oauth) did not match, indicating a possible CSRF attack.
+    #[error("OAuth state parameter mismatch")]
+    OAuthStateMismatch,
+    /// A page of a [resilient](paginated::Paginated::resilient) paginated stream could not be retrieved.
+    #[error("failed to get page: {source}")]
+    Pagination {
+        /// Like [`Page::cursor`](paginated::Page::cursor), but pointing at the page that failed rather than past it. `None` if it was the first page.
+        cursor: Option<paginated::Cursor>,
+        source: Box<Error>,
+    },
     #[error(transparent)] Reqwest(#[from] reqwest::Error),
     #[error("{0}, body:\n\n{1}")]
     ResponseJson(serde_json::Error, String),
+    /// The OAuth token was [revoked](Client::revoke_token) and the credentials can't be used to get a new one.
+    #[error("the OAuth token has been revoked")]
+    TokenRevoked,
+    /// An error returned by a [`TokenStore`].
+    #[error("token store error: {0}")]
+    TokenStore(#[source] Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl Error {
-    fn is_invalid_oauth_token(&self) -> bool {
+    /// Returns the HTTP status code of the response that caused this error, if any.
+    pub fn status(&self) -> Option<StatusCode> {
         match self {
-            Error::HttpStatus(e, _) | Error::Reqwest(e) => e.status().map_or(false, |code| code == StatusCode::UNAUTHORIZED), //TODO check response body to make sure
-            Error::ExactlyOne(_) | Error::InvalidHeaderValue(_) | Error::ResponseJson(_, _) => false,
+            Error::Api { status, .. } => Some(*status),
+            Error::HttpStatus(e, _) | Error::Reqwest(e) => e.status(),
+            Error::Pagination { source, .. } => source.status(),
+            Error::DeviceCodeExpired | Error::ExactlyOne(_) | Error::IdToken(_) | Error::InvalidHeaderValue(_) | Error::MissingScope(_) | Error::OAuthDenied { .. } | Error::OAuthStateMismatch | Error::ResponseJson(_, _) | Error::TokenRevoked | Error::TokenStore(_) => None,
         }
     }
 
-    fn is_spurious_network_error(&self) -> bool {
+    /// Returns `true` if the requested resource does not exist (HTTP 404).
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(StatusCode::NOT_FOUND)
+    }
+
+    /// Returns `true` if the authenticated user is not allowed to perform the request (HTTP 403).
+    pub fn is_forbidden(&self) -> bool {
+        self.status() == Some(StatusCode::FORBIDDEN)
+    }
+
+    /// Returns `true` if the OAuth token is valid but lacks a scope required by the endpoint.
+    pub fn is_missing_scope(&self) -> bool {
         match self {
-            Error::HttpStatus(e, _) | Error::Reqwest(e) => e.status().map_or(false, |code| !code.is_client_error()),
-            Error::ExactlyOne(_) | Error::InvalidHeaderValue(_) | Error::ResponseJson(_, _) => false,
+            Error::Api { status, message, .. } => matches!(*status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) && message.starts_with("Missing scope"),
+            Error::MissingScope(_) => true,
+            Error::Pagination { source, .. } => source.is_missing_scope(),
+            Error::DeviceCodeExpired | Error::ExactlyOne(_) | Error::HttpStatus(_, _) | Error::IdToken(_) | Error::InvalidHeaderValue(_) | Error::OAuthDenied { .. } | Error::OAuthStateMismatch | Error::Reqwest(_) | Error::ResponseJson(_, _) | Error::TokenRevoked | Error::TokenStore(_) => false,
         }
     }
+
+    /// Returns `true` if the request was rejected because the rate limit was exceeded (HTTP 429).
+    pub fn is_rate_limited(&self) -> bool {
+        self.status() == Some(StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    /// Returns `true` if the request conflicts with the current state of the resource (HTTP 409), e.g. because another request is modifying it at the same time.
+    pub fn is_conflict(&self) -> bool {
+        self.status() == Some(StatusCode::CONFLICT)
+    }
+
+    fn is_invalid_oauth_token(&self) -> bool {
+        // Helix uses 401 for missing scopes as well, and those can't be fixed by reauthenticating
+        self.status() == Some(StatusCode::UNAUTHORIZED) && !self.is_missing_scope()
+    }
 }
 
 impl<I: Iterator> From<itertools::ExactlyOneError<I>> for Error {
@@ -76,182 +182,1176 @@ impl<I: Iterator> From<itertools::ExactlyOneError<I>> for Error {
     }
 }
 
+/// The body of an error response, see <https://dev.twitch.tv/docs/api/guide#errors>.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    error: String,
+    message: String,
+}
+
 #[async_trait]
-trait ResponseExt {
+trait ResponseExt: Sized {
+    async fn detailed_error_for_status(self) -> Result<Self, Error>;
     async fn json_with_text_in_error<T: DeserializeOwned>(self) -> Result<T, Error>;
 }
 
 #[async_trait]
 impl ResponseExt for reqwest::Response {
+    async fn detailed_error_for_status(self) -> Result<Self, Error> {
+        match self.error_for_status_ref() {
+            Ok(_) => Ok(self),
+            Err(e) => {
+                let status = self.status();
+                let text = self.text().await;
+                Err(if let Some(ErrorResponse { error, message }) = text.as_ref().ok().and_then(|text| serde_json::from_str(text).ok()) {
+                    Error::Api { status, error, message }
+                } else {
+                    Error::HttpStatus(e, text)
+                })
+            }
+        }
+    }
+
     async fn json_with_text_in_error<T: DeserializeOwned>(self) -> Result<T, Error> {
         let text = self.text().await?;
         serde_json::from_str(&text).map_err(|e| Error::ResponseJson(e, text))
     }
 }
 
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::AppAuth {}
+    impl Sealed for super::UserAuth {}
+}
+
+/// The kind of OAuth token used by [`Credentials`] and a [`Client`], see [`AppAuth`] and [`UserAuth`].
+///
+/// Endpoints which act on behalf of a user require a `Client<UserAuth>`, so calling them with an app access token is a compile-time error. All other endpoints accept either kind.
+///
+/// This trait is sealed and can't be implemented outside of this crate.
+pub trait Auth: sealed::Sealed + Send + Sync + 'static {}
+
+/// Marker for an [app access token](https://dev.twitch.tv/docs/authentication/#app-access-tokens), or any token of unknown kind.
+pub enum AppAuth {}
+
+impl Auth for AppAuth {}
+
+/// Marker for a [user access token](https://dev.twitch.tv/docs/authentication/#user-access-tokens).
+pub enum UserAuth {}
+
+impl Auth for UserAuth {}
+
 /// Info required to use the Twitch API.
 ///
-/// Can be constructed from a client secret and/or an OAuth token, see the docs on the methods for details.
-pub struct Credentials(EitherOrBoth<(String, String), String>); // left = (client_secret, scopes), right = oauth_token
+/// Can be constructed from a client secret and/or an OAuth token, see the docs on the methods for details. The type parameter indicates whether the credentials are for an [app access token](AppAuth) or a [user access token](UserAuth).
+///
+/// This is a handle to shared state, so cloning it is cheap, and clones share the same token and rate limit. This allows keeping the credentials of many users around and using them with [`Client::as_user`].
+pub struct Credentials<A: Auth = AppAuth> {
+    inner: Arc<CredentialsInner>,
+    auth: PhantomData<A>,
+}
+
+impl<A: Auth> Clone for Credentials<A> {
+    fn clone(&self) -> Credentials<A> {
+        Credentials {
+            inner: Arc::clone(&self.inner),
+            auth: PhantomData,
+        }
+    }
+}
+
+/// State which is specific to a token, as opposed to [`ClientInner`], which is shared between all credentials used with a client.
+struct CredentialsInner {
+    token: RwLock<TokenState>,
+    /// Held while a new token is being requested, so that concurrent tasks which need a new token wait for a single request instead of each sending their own.
+    refresh_lock: Mutex<()>,
+    /// Twitch tracks rate limits per token, so this is shared between all requests made with these credentials.
+    rate_limit: Mutex<RateLimit>,
+    /// The result of the last successful validation of the current token.
+    token_info: RwLock<Option<TokenInfo>>,
+}
+
+/// The state of [`Credentials`] which is independent of the kind of token.
+struct TokenState {
+    /// How to get a new token.
+    grant: Option<Grant>,
+    oauth_token: Option<String>,
+    /// When the `oauth_token` expires, if known from the token response or from [validation](Client::validate_token).
+    expires_at: Option<DateTime<Utc>>,
+    token_store: Option<Box<dyn TokenStore>>,
+    /// Whether the token has already been loaded from the `token_store`.
+    token_store_loaded: bool,
+    on_token_revoked: Option<TokenRevokedHook>,
+}
+
+/// A way to obtain a new OAuth token.
+#[derive(Clone)]
+enum Grant {
+    /// An [app access token](https://dev.twitch.tv/docs/authentication/getting-tokens-oauth/#client-credentials-grant-flow).
+    ClientCredentials {
+        client_secret: String,
+        scopes: String,
+    },
+    /// A [user access token](https://dev.twitch.tv/docs/authentication/refresh-tokens/). Twitch may rotate the refresh token when it's used, so it's replaced with the one from the response.
+    RefreshToken {
+        /// `None` for [public clients](https://dev.twitch.tv/docs/authentication/register-app/), which can't keep a secret.
+        client_secret: Option<String>,
+        refresh_token: String,
+    },
+}
+
+impl<A: Auth> Credentials<A> {
+    fn new(grant: Option<Grant>, oauth_token: Option<String>) -> Credentials<A> {
+        Credentials {
+            inner: Arc::new(CredentialsInner {
+                token: RwLock::new(TokenState {
+                    expires_at: None,
+                    token_store: None,
+                    token_store_loaded: false,
+                    on_token_revoked: None,
+                    grant, oauth_token,
+                }),
+                refresh_lock: Mutex::default(),
+                rate_limit: Mutex::default(),
+                token_info: RwLock::default(),
+            }),
+            auth: PhantomData,
+        }
+    }
+
+    /// Persist tokens in the given store.
+    ///
+    /// Before the credentials are first used, the token is loaded from the store, replacing the one given when constructing the credentials. Afterwards, the store is updated whenever a new token is obtained. A user access token is also reloaded before it's refreshed after being rejected, in case another instance sharing the store has already replaced it.
+    ///
+    /// If the credentials (or a clone of them) are in use by a [`Client`], this waits until the current token is no longer being accessed.
+    pub async fn with_token_store(self, token_store: impl TokenStore + 'static) -> Credentials<A> {
+        let mut state = self.inner.token.write().await;
+        state.token_store = Some(Box::new(token_store));
+        state.token_store_loaded = false;
+        drop(state);
+        self
+    }
+
+    /// Sets a function to be called when [token validation](Client::validate_token) finds that the OAuth token of these credentials is no longer valid.
+    ///
+    /// Unlike [`ClientBuilder::on_token_revoked`], this is specific to these credentials, so services which act on behalf of many users using [`Client::as_user`] can tell whose token was revoked. If both are set, both are called.
+    ///
+    /// If the credentials (or a clone of them) are in use by a [`Client`], this waits until the current token is no longer being accessed.
+    pub async fn on_token_revoked(self, f: impl Fn(&Error) + Send + Sync + 'static) -> Credentials<A> {
+        self.inner.token.write().await.on_token_revoked = Some(Box::new(f));
+        self
+    }
+}
 
-impl Credentials {
+impl Credentials<AppAuth> {
     /// Use the given client secret to generate a new OAuth token.
-    pub fn from_client_secret<S: fmt::Display, U: fmt::Display, I: IntoIterator<Item = U>>(client_secret: S, scopes: I) -> Credentials {
-        Credentials(EitherOrBoth::Left((client_secret.to_string(), scopes.into_iter().join(" "))))
+    ///
+    /// The scopes are usually [`oauth::Scope`]s, but anything implementing `Display` is accepted so scopes not yet known to this crate can be requested.
+    pub fn from_client_secret<S: fmt::Display, U: fmt::Display, I: IntoIterator<Item = U>>(client_secret: S, scopes: I) -> Credentials<AppAuth> {
+        Credentials::new(Some(Grant::ClientCredentials { client_secret: client_secret.to_string(), scopes: scopes.into_iter().join(" ") }), None)
     }
 
     /// Use the given OAuth token. When the token expires, the error is passed to the caller.
-    pub fn from_oauth_token(oauth_token: impl fmt::Display) -> Credentials {
-        Credentials(EitherOrBoth::Right(oauth_token.to_string()))
+    ///
+    /// Use [`Credentials::from_user_oauth_token`] instead if this is a user access token which should be used with endpoints that act on behalf of the user.
+    pub fn from_oauth_token(oauth_token: impl fmt::Display) -> Credentials<AppAuth> {
+        Credentials::new(None, Some(oauth_token.to_string()))
     }
 
     /// Use the given OAuth token. When the token expires, use the given client secret to generate a new OAuth token.
-    pub fn from_client_secret_and_oauth_token<S: fmt::Display, U: fmt::Display, I: IntoIterator<Item = U>, T: fmt::Display>(client_secret: S, scopes: I, oauth_token: T) -> Credentials {
-        Credentials(EitherOrBoth::Both((client_secret.to_string(), scopes.into_iter().join(" ")), oauth_token.to_string()))
+    pub fn from_client_secret_and_oauth_token<S: fmt::Display, U: fmt::Display, I: IntoIterator<Item = U>, T: fmt::Display>(client_secret: S, scopes: I, oauth_token: T) -> Credentials<AppAuth> {
+        Credentials::new(Some(Grant::ClientCredentials { client_secret: client_secret.to_string(), scopes: scopes.into_iter().join(" ") }), Some(oauth_token.to_string()))
+    }
+}
+
+impl Credentials<UserAuth> {
+    /// Use the given [user access token](https://dev.twitch.tv/docs/authentication/#user-access-tokens). When it expires, use the refresh token and client secret to get a new one.
+    ///
+    /// This is required for endpoints which act on behalf of a user, like [`Follow::from`](model::Follow::from).
+    pub fn from_user_tokens(access_token: impl fmt::Display, refresh_token: impl fmt::Display, client_secret: impl fmt::Display) -> Credentials<UserAuth> {
+        Credentials::new(Some(Grant::RefreshToken { client_secret: Some(client_secret.to_string()), refresh_token: refresh_token.to_string() }), Some(access_token.to_string()))
     }
 
-    fn set_token(&mut self, token: String) {
-        self.0 = match mem::replace(&mut self.0, EitherOrBoth::Right(String::default())) {
-            EitherOrBoth::Left((client_secret, scopes)) | EitherOrBoth::Both((client_secret, scopes), _) => EitherOrBoth::Both((client_secret, scopes), token),
-            EitherOrBoth::Right(_) => EitherOrBoth::Right(token),
-        };
+    /// Like [`Credentials::from_user_tokens`], but for apps registered as [public clients](https://dev.twitch.tv/docs/authentication/register-app/), which don't have a client secret.
+    pub fn from_public_user_tokens(access_token: impl fmt::Display, refresh_token: impl fmt::Display) -> Credentials<UserAuth> {
+        Credentials::new(Some(Grant::RefreshToken { client_secret: None, refresh_token: refresh_token.to_string() }), Some(access_token.to_string()))
+    }
+
+    /// Use the given [user access token](https://dev.twitch.tv/docs/authentication/#user-access-tokens). When the token expires, the error is passed to the caller.
+    pub fn from_user_oauth_token(oauth_token: impl fmt::Display) -> Credentials<UserAuth> {
+        Credentials::new(None, Some(oauth_token.to_string()))
+    }
+}
+
+impl TokenState {
+    fn expires_soon(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at - Utc::now() < TOKEN_REFRESH_MARGIN)
+    }
+
+    fn needs_load(&self) -> bool {
+        self.token_store.is_some() && !self.token_store_loaded
+    }
+
+    async fn load_stored_token(&mut self) -> Result<(), Error> {
+        if !self.needs_load() { return Ok(()) } // another task loaded the token while we were waiting for the lock
+        self.reload_stored_token().await
+    }
+
+    /// Replaces the token with the one from the `token_store`, which may have been updated by another instance.
+    async fn reload_stored_token(&mut self) -> Result<(), Error> {
+        let Some(token_store) = &self.token_store else { return Ok(()) };
+        if let Some(StoredToken { access_token, refresh_token }) = token_store.load().await? {
+            if self.oauth_token.as_ref() != Some(&access_token) {
+                self.set_token(access_token, refresh_token, None);
+            }
+        }
+        self.token_store_loaded = true;
+        Ok(())
+    }
+
+    async fn save_token(&self) -> Result<(), Error> {
+        if let (Some(token_store), Some(access_token)) = (&self.token_store, &self.oauth_token) {
+            token_store.save(&StoredToken {
+                access_token: access_token.clone(),
+                refresh_token: if let Some(Grant::RefreshToken { refresh_token, .. }) = &self.grant { Some(refresh_token.clone()) } else { None },
+            }).await?;
+        }
+        Ok(())
+    }
+
+    fn set_token(&mut self, token: String, new_refresh_token: Option<String>, expires_at: Option<DateTime<Utc>>) {
+        self.oauth_token = Some(token);
+        self.expires_at = expires_at;
+        if let (Some(Grant::RefreshToken { refresh_token, .. }), Some(new_refresh_token)) = (&mut self.grant, new_refresh_token) {
+            *refresh_token = new_refresh_token;
+        }
+    }
+
+    /// Forgets the current token after it has been revoked.
+    async fn clear_token(&mut self) -> Result<(), Error> {
+        self.oauth_token = None;
+        self.expires_at = None;
+        if let Some(Grant::RefreshToken { .. }) = self.grant {
+            // revoking a user access token also invalidates its refresh token
+            self.grant = None;
+        }
+        if let Some(token_store) = &self.token_store {
+            token_store.clear().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Determines which failed requests a `Client` retries, and how long it waits between attempts.
+///
+/// The default policy makes up to 5 attempts per request, starting with a delay of half a second which doubles after each failed attempt, up to 30 seconds. Server errors (HTTP 500, 502, 503, and 504) and connection errors are retried, and a request is given up on after 2 minutes.
+///
+/// Requests with a non-idempotent method (`POST` and `PATCH`) are only retried if the connection could not be established, since otherwise the request may already have taken effect.
+///
+/// Waiting for the rate limit (HTTP 429) and reauthenticating after an expired OAuth token are handled separately and don't count as attempts.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    deadline: Option<Duration>,
+    retryable_statuses: HashSet<StatusCode>,
+    retry_connection_errors: bool,
+}
+
+impl RetryPolicy {
+    /// A policy which never retries a failed request.
+    pub fn never() -> RetryPolicy {
+        RetryPolicy::default().max_attempts(1)
+    }
+
+    /// Sets the maximum number of attempts for a single request, including the first one. Values below 1 are treated as 1.
+    pub fn max_attempts(mut self, max_attempts: u32) -> RetryPolicy {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry. The delay is doubled after every failed attempt.
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> RetryPolicy {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Sets the upper bound for the delay between two attempts.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> RetryPolicy {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// If enabled (the default), each delay is randomly shortened by up to half so that clients which failed at the same time don't retry in lockstep.
+    pub fn jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the maximum total time spent on a single request, including all retries. `None` means there is no deadline and only `max_attempts` applies.
+    pub fn deadline(mut self, deadline: Option<Duration>) -> RetryPolicy {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Sets the HTTP status codes for which a request is retried.
+    pub fn retryable_statuses(mut self, statuses: impl IntoIterator<Item = StatusCode>) -> RetryPolicy {
+        self.retryable_statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Sets whether requests which failed before receiving a response (e.g. because the connection was refused or timed out) are retried.
+    pub fn retry_connection_errors(mut self, retry_connection_errors: bool) -> RetryPolicy {
+        self.retry_connection_errors = retry_connection_errors;
+        self
+    }
+
+    fn is_retryable(&self, error: &Error) -> bool {
+        match error {
+            Error::Api { status, .. } => self.retryable_statuses.contains(status),
+            Error::HttpStatus(e, _) | Error::Reqwest(e) => if let Some(status) = e.status() {
+                self.retryable_statuses.contains(&status)
+            } else {
+                self.retry_connection_errors && (e.is_connect() || e.is_timeout() || e.is_request())
+            },
+            Error::Pagination { source, .. } => self.is_retryable(source),
+            Error::DeviceCodeExpired | Error::ExactlyOne(_) | Error::IdToken(_) | Error::InvalidHeaderValue(_) | Error::MissingScope(_) | Error::OAuthDenied { .. } | Error::OAuthStateMismatch | Error::ResponseJson(_, _) | Error::TokenRevoked | Error::TokenStore(_) => false,
+        }
+    }
+
+    /// Returns how long to wait before the next attempt, or `None` if the request should not be retried.
+    ///
+    /// `attempt` is the number of attempts made so far and `started` is the time of the first attempt.
+    fn backoff(&self, method: &Method, error: &Error, attempt: u32, started: Instant) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.is_retryable(error) { return None }
+        if !method.is_idempotent() && !matches!(error, Error::Reqwest(e) if e.is_connect()) { return None }
+        let mut delay = self.initial_backoff.saturating_mul(2u32.saturating_pow(attempt - 1)).min(self.max_backoff);
+        if self.jitter {
+            delay = delay.mul_f64(rand::random_range(0.5..=1.0));
+        }
+        if self.deadline.is_some_and(|deadline| started.elapsed() + delay > deadline) { return None }
+        Some(delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            deadline: Some(Duration::from_secs(2 * 60)),
+            retryable_statuses: [
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ].into_iter().collect(),
+            retry_connection_errors: true,
+        }
+    }
+}
+
+/// Bookkeeping for the [Helix rate limit](https://dev.twitch.tv/docs/api/guide#twitch-rate-limits).
+///
+/// Twitch uses a token bucket algorithm. The state of the bucket is reported in the `Ratelimit-*` headers of every response, and we additionally take a point from the bucket for every request we send so concurrent tasks don't all race for the last few points.
+#[derive(Debug, Default)]
+struct RateLimit {
+    /// The size of the bucket, from the `Ratelimit-Limit` header.
+    limit: Option<u32>,
+    /// The number of points left in the bucket, from the `Ratelimit-Remaining` header.
+    remaining: Option<u32>,
+    /// The time when the bucket will be full again, from the `Ratelimit-Reset` header.
+    reset: Option<DateTime<Utc>>,
+}
+
+impl RateLimit {
+    /// Takes a point from the bucket. If the bucket is empty, returns the time when the caller should try again instead.
+    fn acquire(&mut self) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        if self.reset.is_some_and(|reset| reset <= now) {
+            // the bucket has been refilled
+            self.remaining = self.limit;
+            self.reset = None;
+        }
+        match &mut self.remaining {
+            Some(0) => Some(self.reset.unwrap_or_else(|| now + TimeDelta::seconds(1))),
+            Some(remaining) => {
+                *remaining -= 1;
+                None
+            }
+            // we haven't seen any rate limit headers yet
+            None => None,
+        }
+    }
+
+    /// Updates the bucket from the headers of a response.
+    fn update(&mut self, status: StatusCode, headers: &HeaderMap) {
+        fn header<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+            headers.get(name)?.to_str().ok()?.parse().ok()
+        }
+
+        if let Some(limit) = header(headers, "Ratelimit-Limit") {
+            self.limit = Some(limit);
+        }
+        let reset = header(headers, "Ratelimit-Reset").and_then(|reset| Utc.timestamp_opt(reset, 0).single());
+        if let Some(remaining) = header::<u32>(headers, "Ratelimit-Remaining") {
+            self.remaining = Some(if reset.is_some() && reset == self.reset {
+                // responses to concurrent requests can arrive out of order, so don't give back points we already took
+                self.remaining.map_or(remaining, |old_remaining| old_remaining.min(remaining))
+            } else {
+                remaining
+            });
+        }
+        if let Some(reset) = reset {
+            self.reset = Some(reset);
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            // make sure we wait even if the response didn't include the headers
+            self.remaining = Some(0);
+            self.reset.get_or_insert_with(|| Utc::now() + TimeDelta::seconds(1));
+        }
     }
 }
 
 #[derive(Deserialize)]
 struct CredentialsResponse {
     access_token: String,
+    /// Only included for user access tokens.
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+}
+
+type TokenRevokedHook = Box<dyn Fn(&Error) + Send + Sync>;
+
+/// Information about an OAuth token, as returned by [`Client::validate_token`].
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    /// The client ID of the application that the token was issued to.
+    pub client_id: String,
+    /// The login name of the user who authorized the token. `None` for app access tokens.
+    pub login: Option<String>,
+    /// The ID of the user who authorized the token. `None` for app access tokens.
+    pub user_id: Option<model::UserId>,
+    /// The scopes granted to the token. These are kept as strings so that scopes added to the Twitch API after this version of the crate was released don't cause validation to fail.
+    pub scopes: Vec<String>,
+    /// When the token expires, or `None` if it doesn't.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ValidateResponse {
+    client_id: String,
+    login: Option<String>,
+    user_id: Option<model::UserId>,
+    #[serde(default)]
+    scopes: Vec<String>,
+    expires_in: i64,
+}
+
+impl TokenInfo {
+    /// Returns `true` if the given scope was granted to the token.
+    pub fn has_scope(&self, scope: oauth::Scope) -> bool {
+        self.scopes.iter().any(|granted| granted == scope.as_str())
+    }
+}
+
+impl From<ValidateResponse> for TokenInfo {
+    fn from(ValidateResponse { client_id, login, user_id, scopes, expires_in }: ValidateResponse) -> TokenInfo {
+        TokenInfo {
+            client_id, login, user_id, scopes,
+            expires_at: (expires_in > 0).then(|| Utc::now() + TimeDelta::seconds(expires_in)),
+        }
+    }
+}
+
+/// Appends a path to a base URL, regardless of whether the base URL has a trailing slash.
+fn join_url(base: &Url, path: impl fmt::Display) -> String {
+    format!("{}{}", base.as_str().trim_end_matches('/'), path)
+}
+
+/// A builder for a [`Client`], returned by [`Client::builder`].
+pub struct ClientBuilder<A: Auth = AppAuth> {
+    user_agent: Cow<'static, str>,
+    client_id: String,
+    credentials: Credentials<A>,
+    reqwest_client: Option<reqwest::Client>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxies: Vec<reqwest::Proxy>,
+    helix_base_url: Url,
+    kraken_base_url: Url,
+    oauth_base_url: Url,
+    retry_policy: RetryPolicy,
+    on_token_revoked: Option<TokenRevokedHook>,
+}
+
+impl<A: Auth> ClientBuilder<A> {
+    /// Uses the given `reqwest::Client` for all requests instead of constructing a new one, e.g. to share a connection pool with other parts of an application.
+    ///
+    /// If this is set, [`connect_timeout`](ClientBuilder::connect_timeout), [`timeout`](ClientBuilder::timeout), and [`proxy`](ClientBuilder::proxy) are ignored and should be configured on the `reqwest::Client` instead.
+    pub fn reqwest_client(mut self, client: reqwest::Client) -> ClientBuilder<A> {
+        self.reqwest_client = Some(client);
+        self
+    }
+
+    /// Sets a timeout for establishing a connection. By default, there is no timeout.
+    pub fn connect_timeout(mut self, timeout: Duration) -> ClientBuilder<A> {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets a timeout for each individual request, from establishing the connection until the response body has been received. By default, there is no timeout.
+    ///
+    /// Requests which time out are retried according to the [`RetryPolicy`].
+    pub fn timeout(mut self, timeout: Duration) -> ClientBuilder<A> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Adds a proxy to be used for requests. Can be called multiple times, in which case the first matching proxy is used.
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> ClientBuilder<A> {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Overrides the base URL for [Helix API](https://dev.twitch.tv/docs/api) requests, which defaults to `https://api.twitch.tv/helix`.
+    ///
+    /// This can be used to point the client at a mock server such as the one included in the [Twitch CLI](https://dev.twitch.tv/docs/cli/mock-api-command/).
+    pub fn helix_base_url(mut self, url: Url) -> ClientBuilder<A> {
+        self.helix_base_url = url;
+        self
+    }
+
+    /// Overrides the base URL for requests to the legacy Kraken API, which defaults to `https://api.twitch.tv/v5`.
+    pub fn kraken_base_url(mut self, url: Url) -> ClientBuilder<A> {
+        self.kraken_base_url = url;
+        self
+    }
+
+    /// Overrides the base URL for [authentication](https://dev.twitch.tv/docs/authentication) requests, which defaults to `https://id.twitch.tv/oauth2`.
+    pub fn oauth_base_url(mut self, url: Url) -> ClientBuilder<A> {
+        self.oauth_base_url = url;
+        self
+    }
+
+    /// Sets the policy used for retrying requests which failed due to transient errors. See [`RetryPolicy`] for the default.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> ClientBuilder<A> {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets a function to be called when [token validation](Client::validate_token) finds that the OAuth token is no longer valid, e.g. because the user disconnected the app or changed their password.
+    ///
+    /// This is called for all credentials used with this client, including those passed to [`Client::as_user`]. Use [`Credentials::on_token_revoked`] to handle revocation for specific credentials.
+    pub fn on_token_revoked(mut self, f: impl Fn(&Error) + Send + Sync + 'static) -> ClientBuilder<A> {
+        self.on_token_revoked = Some(Box::new(f));
+        self
+    }
+
+    /// Constructs the `Client`.
+    pub fn build(self) -> Result<Client<A>, Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(reqwest::header::USER_AGENT, match self.user_agent {
+            Cow::Borrowed(user_agent) => HeaderValue::from_static(user_agent),
+            Cow::Owned(user_agent) => HeaderValue::try_from(user_agent)?,
+        });
+        headers.insert("Client-ID", HeaderValue::from_str(&self.client_id)?);
+        let client = if let Some(client) = self.reqwest_client {
+            client
+        } else {
+            let mut builder = reqwest::Client::builder();
+            if let Some(connect_timeout) = self.connect_timeout {
+                builder = builder.connect_timeout(connect_timeout);
+            }
+            if let Some(timeout) = self.timeout {
+                builder = builder.timeout(timeout);
+            }
+            for proxy in self.proxies {
+                builder = builder.proxy(proxy);
+            }
+            builder.build()?
+        };
+        Ok(Client {
+            inner: Arc::new(ClientInner {
+                client_id: self.client_id,
+                client,
+                headers,
+                helix_base_url: self.helix_base_url,
+                kraken_base_url: self.kraken_base_url,
+                oauth_base_url: self.oauth_base_url,
+                retry_policy: self.retry_policy,
+                on_token_revoked: self.on_token_revoked,
+            }),
+            credentials: self.credentials.inner,
+            auth: PhantomData,
+        })
+    }
 }
 
 /// The entry point to the API.
-pub struct Client<'a> {
+///
+/// This is a handle to shared state, so cloning it is cheap, and clones share the same credentials and rate limit.
+///
+/// The type parameter indicates the kind of [`Credentials`] the client was constructed with, see [`Auth`].
+pub struct Client<A: Auth = AppAuth> {
+    inner: Arc<ClientInner>,
+    credentials: Arc<CredentialsInner>,
+    auth: PhantomData<A>,
+}
+
+impl<A: Auth> Clone for Client<A> {
+    fn clone(&self) -> Client<A> {
+        Client {
+            inner: Arc::clone(&self.inner),
+            credentials: Arc::clone(&self.credentials),
+            auth: PhantomData,
+        }
+    }
+}
+
+struct ClientInner {
     client: reqwest::Client,
-    client_id: Cow<'a, str>,
-    /// If we're currently being rate limited, this has the time when the API can be called again.
-    rate_limit_reset: Option<DateTime<Utc>>,
-    credentials: Arc<RwLock<Credentials>>,
+    /// Sent with every request. These are not set as the `reqwest::Client`'s default headers since the client may have been supplied by the user.
+    headers: HeaderMap,
+    client_id: String,
+    helix_base_url: Url,
+    kraken_base_url: Url,
+    oauth_base_url: Url,
+    retry_policy: RetryPolicy,
+    on_token_revoked: Option<TokenRevokedHook>,
 }
 
-impl<'a> Client<'a> {
+impl<A: Auth> Client<A> {
     /// Constructs a new `Client` for accessing the [Helix API](https://dev.twitch.tv/docs/api).
     ///
     /// The `user_agent` parameter is used as the `User-Agent` header for all requests. It must be a `&'static str` for performance reasons.
     ///
     /// The remaining parameters of this constructor reflect that [as of April 30, 2020, all Helix endpoints require OAuth tokens](https://discuss.dev.twitch.tv/t/requiring-oauth-for-helix-twitch-api-endpoints/23916).
-    pub fn new(user_agent: &'static str, client_id: impl Into<Cow<'a, str>>, credentials: Credentials) -> Result<Client<'a>, Error> {
-        let client_id = client_id.into();
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(reqwest::header::USER_AGENT, reqwest::header::HeaderValue::from_static(user_agent));
-        headers.insert("Client-ID", reqwest::header::HeaderValue::from_str(&client_id)?);
-        Ok(Client {
-            client_id,
-            client: reqwest::Client::builder()
-                .default_headers(headers)
-                .build()?,
-            rate_limit_reset: None,
-            credentials: Arc::new(RwLock::new(credentials)),
-        })
+    ///
+    /// Use [`Client::builder`] to configure the client further.
+    pub fn new(user_agent: &'static str, client_id: impl Into<String>, credentials: Credentials<A>) -> Result<Client<A>, Error> {
+        Client::builder(user_agent, client_id, credentials).build()
+    }
+
+    /// Returns a [`ClientBuilder`] which can be used to configure a `Client`. The parameters are the same as for [`Client::new`], except that `user_agent` may also be an owned `String`.
+    pub fn builder(user_agent: impl Into<Cow<'static, str>>, client_id: impl Into<String>, credentials: Credentials<A>) -> ClientBuilder<A> {
+        ClientBuilder {
+            user_agent: user_agent.into(),
+            client_id: client_id.into(),
+            credentials,
+            reqwest_client: None,
+            connect_timeout: None,
+            timeout: None,
+            proxies: Vec::default(),
+            helix_base_url: Url::parse(HELIX_BASE_URL).expect("failed to parse default Helix base URL"),
+            kraken_base_url: Url::parse(KRAKEN_BASE_URL).expect("failed to parse default Kraken base URL"),
+            oauth_base_url: Url::parse(OAUTH_BASE_URL).expect("failed to parse default OAuth base URL"),
+            retry_policy: RetryPolicy::default(),
+            on_token_revoked: None,
+        }
+    }
+
+    pub(crate) fn helix_url(&self, path: impl fmt::Display) -> String {
+        join_url(&self.inner.helix_base_url, path)
+    }
+
+    pub(crate) fn kraken_url(&self, path: impl fmt::Display) -> String {
+        join_url(&self.inner.kraken_base_url, path)
+    }
+
+    fn oauth_url(&self, path: impl fmt::Display) -> String {
+        join_url(&self.inner.oauth_base_url, path)
+    }
+
+    /// Returns a handle to the same client with a different [`Auth`] marker. Only used for requests which don't depend on the kind of token, like the [OAuth flows](oauth).
+    fn cast<B: Auth>(&self) -> Client<B> {
+        Client {
+            inner: Arc::clone(&self.inner),
+            credentials: Arc::clone(&self.credentials),
+            auth: PhantomData,
+        }
+    }
+
+    /// Returns a handle to this client which uses the given user credentials instead of the ones it was constructed with.
+    ///
+    /// The returned client shares the connection pool and configuration of this one, but has the token, rate limit, and [token info](Client::token_info) of the given credentials. This is useful for services which act on behalf of many users.
+    pub fn as_user(&self, credentials: &Credentials<UserAuth>) -> Client<UserAuth> {
+        Client {
+            inner: Arc::clone(&self.inner),
+            credentials: Arc::clone(&credentials.inner),
+            auth: PhantomData,
+        }
+    }
+
+    fn http_request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.inner.client.request(method, url).headers(self.inner.headers.clone())
     }
 
     /*
     pub(crate) async fn get<U: fmt::Display, T: DeserializeOwned>(&self, url: U) -> Result<T, Error> {
-        self.get_abs(&format!("{}{}", HELIX_BASE_URL, url)).await
+        self.get_abs(&self.helix_url(url)).await
     }
 
     pub(crate) async fn get_abs<U: IntoUrl, T: DeserializeOwned>(&self, url: U) -> Result<T, Error> {
-        self.get_abs_query(url, &Vec::<(String, String)>::default()).await
+        Ok(self.get_raw::<_, _, _, _, ResponseData<_>>(url, &Vec::<(String, String)>::default()).await?.data)
     }
     */
 
     pub(crate) async fn get_query<U: fmt::Display, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, T: DeserializeOwned>(&self, url: U, query: Q) -> Result<T, Error>
     where Q::Item: Borrow<(K, V)> {
-        self.get_abs_query(&format!("{}{}", HELIX_BASE_URL, url), query).await
+        self.request(Method::GET, url, query, None::<&()>).await
     }
 
-    pub(crate) async fn get_abs_query<U: IntoUrl, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, T: DeserializeOwned>(&self, url: U, query: Q) -> Result<T, Error>
+    pub(crate) async fn get_raw<U: IntoUrl, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, T: DeserializeOwned>(&self, url: U, query: Q) -> Result<T, Error>
     where Q::Item: Borrow<(K, V)> {
-        Ok(self.get_raw::<_, _, _, _, ResponseData<_>>(url, query).await?.data)
+        self.request_raw(Method::GET, url, query, None::<&()>).await
     }
 
-    pub(crate) async fn get_raw<U: IntoUrl, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, T: DeserializeOwned>(&self, url: U, query: Q) -> Result<T, Error>
+    /// Sends a request to an arbitrary Helix endpoint and returns the `data` field of the response.
+    ///
+    /// This can be used to call endpoints which don't have first-class support in this crate yet. `path` is relative to the Helix base URL, e.g. `"/channels"`. If `body` is given, it's sent as JSON; use `None::<&()>` for requests without a body. Authentication, rate limits, and retries are handled the same way as for the built-in endpoints.
+    ///
+    /// For endpoints which respond with `204 No Content`, use [`Client::request_no_content`] instead. For paginated endpoints, use [`Client::request_paginated`].
+    pub async fn request<U: fmt::Display, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, B: Serialize + ?Sized, T: DeserializeOwned>(&self, method: Method, path: U, query: Q, body: Option<&B>) -> Result<T, Error>
+    where Q::Item: Borrow<(K, V)> {
+        Ok(self.request_raw::<_, _, _, _, _, ResponseData<_>>(method, &self.helix_url(path), query, body).await?.data)
+    }
+
+    /// Like [`Client::request`], but for endpoints which respond with `204 No Content`.
+    pub async fn request_no_content<U: fmt::Display, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, B: Serialize + ?Sized>(&self, method: Method, path: U, query: Q, body: Option<&B>) -> Result<(), Error>
+    where Q::Item: Borrow<(K, V)> {
+        self.send(method, &self.helix_url(path), query, body).await?;
+        Ok(())
+    }
+
+    /// Sends a `GET` request to an arbitrary paginated Helix endpoint and returns a stream of all items in the `data` fields of the responses.
+    ///
+    /// `path` is relative to the Helix base URL, e.g. `"/streams"`. The pagination cursor is handled automatically and must not be included in `query`, and neither should the page size, which can be set using [`Paginated::page_size`](paginated::Paginated::page_size).
+    pub fn request_paginated<U: fmt::Display, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, T: DeserializeOwned + Send + 'static>(&self, path: U, query: Q) -> paginated::Paginated<T>
+    where Q::Item: Borrow<(K, V)> {
+        let query = query.into_iter().map(|item| {
+            let (k, v) = item.borrow();
+            (k.as_ref().to_owned(), v.as_ref().to_owned())
+        }).collect();
+        paginated::Paginated::new(self, self.helix_url(path), query)
+    }
+
+    pub(crate) async fn request_raw<U: IntoUrl, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, B: Serialize + ?Sized, T: DeserializeOwned>(&self, method: Method, url: U, query: Q, body: Option<&B>) -> Result<T, Error>
+    where Q::Item: Borrow<(K, V)> {
+        self.send(method, url, query, body).await?.json_with_text_in_error().await
+    }
+
+    /// Like [`Client::get_raw`], but failed requests aren't retried according to the [`RetryPolicy`]. Used by callers which apply the policy themselves.
+    pub(crate) async fn get_raw_without_retries<U: IntoUrl, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, T: DeserializeOwned>(&self, url: U, query: Q) -> Result<T, Error>
+    where Q::Item: Borrow<(K, V)> {
+        self.send_with_retries(Method::GET, url, query, None::<&()>, false).await?.json_with_text_in_error().await
+    }
+
+    /// Sends a request, handling authentication, rate limits, and retries, and returns the response if it was successful.
+    async fn send<U: IntoUrl, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, B: Serialize + ?Sized>(&self, method: Method, url: U, query: Q, body: Option<&B>) -> Result<reqwest::Response, Error>
+    where Q::Item: Borrow<(K, V)> {
+        self.send_with_retries(method, url, query, body, true).await
+    }
+
+    /// Like [`Client::send`], but failed requests are only retried according to the [`RetryPolicy`] if `retry` is `true`. Rate limits and reauthentication are handled either way.
+    async fn send_with_retries<U: IntoUrl, K: AsRef<str>, V: AsRef<str>, Q: IntoIterator, B: Serialize + ?Sized>(&self, method: Method, url: U, query: Q, body: Option<&B>, retry: bool) -> Result<reqwest::Response, Error>
     where Q::Item: Borrow<(K, V)> {
-        let mut token = self.get_oauth_token(None).await?;
         let mut url = url.into_url()?;
         url.query_pairs_mut().extend_pairs(query);
-        Ok(loop {
+        let mut token = self.get_oauth_token(None).await?;
+        let started = Instant::now();
+        let mut attempt = 0;
+        let mut reauthenticated = false;
+        loop {
             // wait for rate limit
-            if let Some(rate_limit_reset) = self.rate_limit_reset {
+            let rate_limit_reset = self.credentials.rate_limit.lock().await.acquire();
+            if let Some(rate_limit_reset) = rate_limit_reset {
                 if let Ok(duration) = (rate_limit_reset - Utc::now()).to_std() {
                     sleep(duration).await;
-                    continue
                 }
+                continue
             }
             // send request
-            let response_data = self.client.get(url.clone())
-                .bearer_auth(&token)
+            attempt += 1;
+            let mut request = self.http_request(method.clone(), url.clone())
+                .bearer_auth(&token);
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let response = request
                 .send().map_err(Error::Reqwest)
                 .and_then(|resp| async {
-                    match resp.error_for_status_ref() {
-                        Ok(_) => Ok(resp),
-                        Err(e) => Err(Error::HttpStatus(e, resp.text().await)),
-                    }
+                    self.credentials.rate_limit.lock().await.update(resp.status(), resp.headers());
+                    resp.detailed_error_for_status().await
                 })
                 .await;
-            match response_data {
-                Ok(data) => break data.json_with_text_in_error().await?,
-                Err(e) => if e.is_spurious_network_error() {
-                    // simply try again
-                } else if e.is_invalid_oauth_token() {
-                    token = self.get_oauth_token(Some(e)).await?;
+            match response {
+                Ok(response) => break Ok(response),
+                Err(e) => if e.is_rate_limited() {
+                    // the bucket has been marked as empty, so the next iteration waits for it to be refilled
+                    attempt -= 1;
+                } else if e.is_invalid_oauth_token() && !reauthenticated {
+                    // only reauthenticate once, a fresh token being rejected won't be fixed by trying again
+                    token = self.oauth_token(Some(e), Some(&token)).await?;
+                    reauthenticated = true;
+                    attempt -= 1;
+                } else if let Some(delay) = retry.then(|| self.inner.retry_policy.backoff(&method, &e, attempt, started)).flatten() {
+                    sleep(delay).await;
                 } else {
-                    return Err(e)
+                    break Err(e)
                 },
             }
-            let response = self.client.get(url.clone())
-                .bearer_auth(&token)
-                .send().await?;
-            if let Err(e) = response.error_for_status_ref() {
-                return Err(Error::HttpStatus(e, response.text().await))
-            }
-            break response.json_with_text_in_error().await?
-        })
+        }
     }
 
     /// Returns an OAuth token from the credentials with which this `Client` was constructed. If no token is cached, a new one is created by authenticating with Twitch.
     ///
     /// The optional parameter `from_error` can be passed to handle an “invalid OAuth token” error by reauthenticating. Other errors are returned transparently.
+    ///
+    /// If the cached token is known to expire soon, a new one is requested ahead of time. Only one request for a new token is sent at a time; concurrent callers wait for it and then use its result.
     pub async fn get_oauth_token(&self, from_error: Option<Error>) -> Result<String, Error> {
-        if from_error.as_ref().map_or(false, |e| !e.is_invalid_oauth_token()) {
+        self.oauth_token(from_error, None).await
+    }
+
+    /// Like [`Client::get_oauth_token`], but `stale_token` is the token which was rejected with `from_error`, if known. If the cached token differs from it, another task has already reauthenticated.
+    async fn oauth_token(&self, from_error: Option<Error>, stale_token: Option<&str>) -> Result<String, Error> {
+        if from_error.as_ref().is_some_and(|e| !e.is_invalid_oauth_token()) {
             // return non-auth errors transparently
             return Err(from_error.expect("just checked"))
         }
-        let response = match (from_error, &self.credentials.read().await.0) {
-            // we have a cached token and no auth error, so just return that
-            (None, EitherOrBoth::Right(oauth_token)) | (None, EitherOrBoth::Both(_, oauth_token)) => return Ok(oauth_token.to_owned()),
+        if self.credentials.token.read().await.needs_load() {
+            self.credentials.token.write().await.load_stored_token().await?;
+        }
+        let proactive = from_error.is_none();
+        let credentials = self.credentials.token.read().await;
+        let stale_token = match (from_error, &credentials.grant, &credentials.oauth_token) {
+            // we have a cached token, no auth error, and no way to get a new token ahead of time, so just return that
+            (None, None, Some(oauth_token)) => return Ok(oauth_token.clone()),
+            // we have a cached token which isn't about to expire and no auth error, so just return that
+            (None, Some(_), Some(oauth_token)) if !credentials.expires_soon() => return Ok(oauth_token.clone()),
             // there was an auth error but we only have a token, no client ID/secret, so we're unable to reauth
-            (Some(e), EitherOrBoth::Right(_)) => return Err(e),
-            // there was an auth error, so reauth
-            (_, EitherOrBoth::Left((client_secret, scopes))) | (Some(_), EitherOrBoth::Both((client_secret, scopes), _)) => {
-                self.client.post("https://id.twitch.tv/oauth2/token")
-                    .query(&[
-                        ("client_id", &*self.client_id),
-                        ("client_secret", client_secret),
-                        ("grant_type", "client_credentials"),
-                        ("scope", scopes),
-                    ])
-                    .send().await?
+            (Some(e), None, Some(_)) => return Err(e),
+            // the token was revoked and we're unable to reauth
+            (_, None, None) => return Err(Error::TokenRevoked),
+            // there was an auth error, no token, or the token is about to expire, so reauth
+            (_, Some(_), oauth_token) => stale_token.map(str::to_owned).or_else(|| oauth_token.clone()),
+        };
+        drop(credentials);
+        let _refresh_guard = self.credentials.refresh_lock.lock().await;
+        if !proactive && matches!(self.credentials.token.read().await.grant, Some(Grant::RefreshToken { .. })) {
+            // another instance sharing the token store may have rotated the refresh token or already gotten a new access token
+            self.credentials.token.write().await.reload_stored_token().await?;
+        }
+        let credentials = self.credentials.token.read().await;
+        if let Some(oauth_token) = &credentials.oauth_token {
+            if stale_token.as_ref() != Some(oauth_token) || proactive && !credentials.expires_soon() {
+                // another task got a new token while we were waiting for the lock
+                return Ok(oauth_token.clone())
             }
+        }
+        let Some(grant) = credentials.grant.clone() else { return Err(Error::TokenRevoked) };
+        // don't block other tasks from reading the token while the request is in flight
+        drop(credentials);
+        let response = self.request_token(&grant).await;
+        let CredentialsResponse { access_token: new_token, refresh_token, expires_in } = match (response, stale_token) {
+            (Ok(response), _) => response,
+            // the old token is still valid for a bit, so refreshing it early can be retried on the next request
+            (Err(_), Some(stale_token)) if proactive => return Ok(stale_token),
+            (Err(e), _) => return Err(e),
         };
-        if let Err(e) = response.error_for_status_ref() {
-            return Err(Error::HttpStatus(e, response.text().await))
+        let mut credentials = self.credentials.token.write().await;
+        if credentials.grant.is_none() {
+            // the token was revoked while we were requesting a new one
+            return Err(Error::TokenRevoked)
         }
-        let new_token = response.json_with_text_in_error::<CredentialsResponse>().await?.access_token;
-        self.credentials.write().await.set_token(new_token.clone()); // cache the new token
+        credentials.set_token(new_token.clone(), refresh_token, expires_in.filter(|&expires_in| expires_in > 0).map(|expires_in| Utc::now() + TimeDelta::seconds(expires_in))); // cache the new token
+        credentials.save_token().await?;
+        drop(credentials);
+        *self.credentials.token_info.write().await = None; // the info was about the old token
         Ok(new_token)
     }
+
+    async fn request_token(&self, grant: &Grant) -> Result<CredentialsResponse, Error> {
+        // sent as a form body rather than in the query string so the secrets don't end up in access logs
+        let form = match grant {
+            Grant::ClientCredentials { client_secret, scopes } => vec![
+                ("client_id", &*self.inner.client_id),
+                ("client_secret", client_secret),
+                ("grant_type", "client_credentials"),
+                ("scope", scopes),
+            ],
+            Grant::RefreshToken { client_secret, refresh_token } => {
+                let mut form = vec![
+                    ("client_id", &*self.inner.client_id),
+                    ("grant_type", "refresh_token"),
+                    ("refresh_token", refresh_token),
+                ];
+                if let Some(client_secret) = client_secret {
+                    form.push(("client_secret", client_secret));
+                }
+                form
+            }
+        };
+        self.http_request(Method::POST, self.oauth_url("/token"))
+            .form(&form)
+            .send().await?
+            .detailed_error_for_status().await?
+            .json_with_text_in_error().await
+    }
+
+    /// [Revokes](https://dev.twitch.tv/docs/authentication/revoke-tokens/) the current OAuth token and removes it from this client.
+    ///
+    /// If the client was constructed with a client secret for an app access token, the next request generates a new token. Otherwise, the next request fails with [`Error::TokenRevoked`].
+    ///
+    /// If the revocation request fails, the token is kept so revoking it can be retried. Returns [`Error::TokenRevoked`] if there is no token to revoke.
+    pub async fn revoke_token(&self) -> Result<(), Error> {
+        // held until the token has been revoked or restored, so tasks which need a token wait instead of requesting a new one in the meantime
+        let _refresh_guard = self.credentials.refresh_lock.lock().await;
+        let mut credentials = self.credentials.token.write().await;
+        // revoke the stored token rather than the one the credentials were constructed with
+        credentials.load_stored_token().await?;
+        // take the token out so no other task can use it while it's being revoked, without blocking them for the duration of the request
+        let Some(oauth_token) = credentials.oauth_token.take() else { return Err(Error::TokenRevoked) };
+        let expires_at = credentials.expires_at;
+        drop(credentials);
+        let response = self.http_request(Method::POST, self.oauth_url("/revoke"))
+            .form(&[
+                ("client_id", &*self.inner.client_id),
+                ("token", &oauth_token),
+            ])
+            .send().map_err(Error::Reqwest)
+            .and_then(|resp| resp.detailed_error_for_status())
+            .await;
+        let mut credentials = self.credentials.token.write().await;
+        match response {
+            Ok(_) => {}
+            // the token was already invalid, which is just as good
+            Err(Error::Api { status: StatusCode::BAD_REQUEST, message, .. }) if message == "Invalid token" => {}
+            Err(e) => {
+                // the token is still valid, so put it back
+                if credentials.oauth_token.is_none() {
+                    credentials.oauth_token = Some(oauth_token);
+                    credentials.expires_at = expires_at;
+                }
+                return Err(e)
+            }
+        }
+        credentials.clear_token().await?;
+        drop(credentials);
+        *self.credentials.token_info.write().await = None;
+        Ok(())
+    }
+
+    /// [Validates](https://dev.twitch.tv/docs/authentication/validate-tokens) the current OAuth token and returns information about it.
+    ///
+    /// If the token is no longer valid, the functions set with [`ClientBuilder::on_token_revoked`] and [`Credentials::on_token_revoked`] are called and the error is returned.
+    pub async fn validate_token(&self) -> Result<TokenInfo, Error> {
+        let token = self.get_oauth_token(None).await?;
+        let response = self.http_request(Method::GET, self.oauth_url("/validate"))
+            .header(reqwest::header::AUTHORIZATION, format!("OAuth {token}"))
+            .send().map_err(Error::Reqwest)
+            .and_then(|resp| resp.detailed_error_for_status())
+            .await;
+        let info = match response {
+            Ok(response) => TokenInfo::from(response.json_with_text_in_error::<ValidateResponse>().await?),
+            Err(e) => {
+                if e.is_invalid_oauth_token() {
+                    if let Some(on_token_revoked) = &self.inner.on_token_revoked {
+                        on_token_revoked(&e);
+                    }
+                    if let Some(on_token_revoked) = &self.credentials.token.read().await.on_token_revoked {
+                        on_token_revoked(&e);
+                    }
+                }
+                return Err(e)
+            }
+        };
+        let mut credentials = self.credentials.token.write().await;
+        if credentials.oauth_token.as_ref() == Some(&token) {
+            // only keep the info if the token hasn't been replaced in the meantime
+            credentials.expires_at = info.expires_at;
+            *self.credentials.token_info.write().await = Some(info.clone());
+        }
+        Ok(info)
+    }
+
+    /// Returns the result of the last successful [token validation](Client::validate_token), unless the token has changed since.
+    pub async fn token_info(&self) -> Option<TokenInfo> {
+        self.credentials.token_info.read().await.clone()
+    }
+
+    /// Returns [`Error::MissingScope`] if the current token doesn't have the given scope. The token is validated first if it hasn't been already.
+    pub(crate) async fn require_scope(&self, scope: oauth::Scope) -> Result<(), Error> {
+        let info = if let Some(info) = self.token_info().await {
+            info
+        } else {
+            self.validate_token().await?
+        };
+        if info.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(Error::MissingScope(scope))
+        }
+    }
+
+    /// Spawns a task on the current Tokio runtime which [validates](Client::validate_token) the OAuth token immediately and then once every `period`.
+    ///
+    /// Twitch [requires](https://dev.twitch.tv/docs/authentication/validate-tokens/#how-often-should-you-validate-tokens) apps to validate their tokens hourly. If the token turns out to be invalid or has been [revoked](Client::revoke_token), the task tries to reauthenticate and stops if that's not possible. It also stops once all clones of this `Client` have been dropped. Other errors are ignored.
+    ///
+    /// # Panics
+    ///
+    /// If called outside of a Tokio runtime, or if `period` is zero.
+    pub fn spawn_revalidation(&self, period: Duration) -> JoinHandle<()> {
+        assert!(!period.is_zero(), "revalidation period must be non-zero");
+        let inner = Arc::downgrade(&self.inner);
+        let credentials = Arc::downgrade(&self.credentials);
+        tokio::spawn(async move {
+            let mut interval = interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let (Some(inner), Some(credentials)) = (Weak::upgrade(&inner), Weak::upgrade(&credentials)) else { break };
+                let client = Client::<A> { inner, credentials, auth: PhantomData };
+                match client.validate_token().await {
+                    Ok(_) => {}
+                    // the token was revoked and there's no way to get a new one
+                    Err(Error::TokenRevoked) => break,
+                    Err(e) => if e.is_invalid_oauth_token() && client.get_oauth_token(Some(e)).await.is_err() {
+                        break
+                    },
+                }
+            }
+        })
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct ResponseData<T> {
     data: T,
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        std::time::{
+            Duration,
+            Instant,
+        },
+        chrono::{
+            TimeDelta,
+            prelude::*,
+        },
+        reqwest::{
+            Method,
+            StatusCode,
+            header::HeaderMap,
+        },
+        super::{
+            Error,
+            RateLimit,
+            RetryPolicy,
+        },
+    };
+
+    fn server_error() -> Error {
+        Error::Api {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            error: "Service Unavailable".to_owned(),
+            message: String::default(),
+        }
+    }
+
+    fn connect_error() -> Error {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().expect("failed to build runtime");
+        // nothing listens on port 1, so the connection is refused
+        let e = runtime.block_on(reqwest::Client::new().get("http://127.0.0.1:1/").send()).expect_err("connected to port 1");
+        assert!(e.is_connect());
+        Error::Reqwest(e)
+    }
+
+    fn rate_limit_headers(limit: u32, remaining: u32, reset: DateTime<Utc>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("ratelimit-limit", limit.into());
+        headers.insert("ratelimit-remaining", remaining.into());
+        headers.insert("ratelimit-reset", reset.timestamp().into());
+        headers
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RetryPolicy::default()
+            .max_attempts(10)
+            .initial_backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(5))
+            .jitter(false)
+            .deadline(None);
+        let started = Instant::now();
+        let delays = (1..=5).map(|attempt| policy.backoff(&Method::GET, &server_error(), attempt, started)).collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 5, 5].map(|secs| Some(Duration::from_secs(secs))));
+        assert_eq!(policy.backoff(&Method::GET, &server_error(), 10, started), None);
+    }
+
+    #[test]
+    fn backoff_respects_deadline() {
+        let policy = RetryPolicy::default()
+            .initial_backoff(Duration::from_secs(2))
+            .jitter(false)
+            .deadline(Some(Duration::from_secs(3)));
+        let started = Instant::now() - Duration::from_secs(2);
+        // waiting 2 more seconds would exceed the deadline
+        assert_eq!(policy.backoff(&Method::GET, &server_error(), 1, started), None);
+        assert_eq!(policy.backoff(&Method::GET, &server_error(), 1, Instant::now()), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn backoff_retries_non_idempotent_only_on_connect_errors() {
+        let policy = RetryPolicy::default().jitter(false);
+        let started = Instant::now();
+        assert!(policy.backoff(&Method::GET, &server_error(), 1, started).is_some());
+        assert_eq!(policy.backoff(&Method::POST, &server_error(), 1, started), None);
+        assert_eq!(policy.backoff(&Method::PATCH, &server_error(), 1, started), None);
+        let connect_error = connect_error();
+        assert!(policy.backoff(&Method::POST, &connect_error, 1, started).is_some());
+        assert!(policy.backoff(&Method::GET, &connect_error, 1, started).is_some());
+    }
+
+    #[test]
+    fn rate_limit_refills_after_reset() {
+        let mut rate_limit = RateLimit::default();
+        rate_limit.update(StatusCode::OK, &rate_limit_headers(800, 0, Utc::now() + TimeDelta::minutes(1)));
+        assert!(rate_limit.acquire().is_some());
+        rate_limit.update(StatusCode::OK, &rate_limit_headers(800, 0, Utc::now() - TimeDelta::seconds(1)));
+        assert!(rate_limit.acquire().is_none());
+        assert_eq!(rate_limit.remaining, Some(799));
+        assert_eq!(rate_limit.reset, None);
+    }
+
+    #[test]
+    fn rate_limit_ignores_out_of_order_responses() {
+        let reset = Utc::now() + TimeDelta::minutes(1);
+        let mut rate_limit = RateLimit::default();
+        rate_limit.update(StatusCode::OK, &rate_limit_headers(800, 10, reset));
+        assert!(rate_limit.acquire().is_none());
+        assert!(rate_limit.acquire().is_none());
+        // a response to a request sent before the last one arrives late
+        rate_limit.update(StatusCode::OK, &rate_limit_headers(800, 9, reset));
+        assert_eq!(rate_limit.remaining, Some(8));
+        // once the bucket is reset, the reported value is used as is
+        rate_limit.update(StatusCode::OK, &rate_limit_headers(800, 799, reset + TimeDelta::minutes(1)));
+        assert_eq!(rate_limit.remaining, Some(799));
+    }
+
+    #[test]
+    fn rate_limit_waits_after_429_without_headers() {
+        let mut rate_limit = RateLimit::default();
+        rate_limit.update(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new());
+        let retry_at = rate_limit.acquire().expect("rate limit should be exhausted");
+        assert!(retry_at > Utc::now());
+    }
+}
@@ -23,10 +23,15 @@ use {
     serde_json::Value as Json,
     url::Url,
     crate::{
+        Auth,
         Client,
         Error,
-        HELIX_BASE_URL,
-        paginated,
+        UserAuth,
+        oauth::Scope,
+        paginated::{
+            Keyed,
+            Paginated,
+        },
     },
 };
 
@@ -93,11 +98,20 @@ impl Follow {
     ///
     /// Returns a list of all channels followed by the authenticated user.
     ///
-    /// Requires a [user access token](https://dev.twitch.tv/docs/authentication/#user-access-tokens) that includes the `user:read:follows` scope.
+    /// Requires a [user access token](https://dev.twitch.tv/docs/authentication/#user-access-tokens) that includes the `user:read:follows` scope. If the validated token doesn't include it, the stream yields [`Error::MissingScope`] without contacting the endpoint.
     ///
     /// The `user_id` parameter must be the ID of the authenticated user.
-    pub fn from<'a>(client: &'a Client<'a>, user_id: UserId) -> impl futures::Stream<Item = Result<Follow, Error>> + 'a {
-        paginated::stream(client, format!("{}/channels/followed", HELIX_BASE_URL), vec![(format!("user_id"), user_id.to_string())])
+    pub fn from(client: &Client<UserAuth>, user_id: UserId) -> Paginated<Follow> {
+        Paginated::new(client, client.helix_url("/channels/followed"), vec![("user_id".to_owned(), user_id.to_string())])
+            .require_scope(Scope::UserReadFollows)
+    }
+}
+
+impl Keyed for Follow {
+    type Key = UserId;
+
+    fn key(&self) -> &UserId {
+        &self.broadcaster_id
     }
 }
 
@@ -112,9 +126,18 @@ pub struct Game {
 impl Game {
     /// <https://dev.twitch.tv/docs/api/reference#get-games>
     ///
-    /// Returns the games with the given IDs in arbitrary order. A maximum of 100 game IDs may be given.
-    pub fn list<'a>(client: &'a Client<'a>, ids: HashSet<GameId>) -> impl futures::Stream<Item = Result<Game, Error>> + 'a {
-        paginated::stream(client, format!("{}/games", HELIX_BASE_URL), ids.into_iter().map(|game_id| (format!("id"), game_id.0)).collect())
+    /// Returns the games with the given IDs in arbitrary order. If more than 100 game IDs are given, they are requested in batches.
+    pub fn list<A: Auth>(client: &Client<A>, ids: HashSet<GameId>) -> Paginated<Game> {
+        Paginated::new(client, client.helix_url("/games"), Vec::default())
+            .batched("id", ids.into_iter().map(|game_id| game_id.0), 100)
+    }
+}
+
+impl Keyed for Game {
+    type Key = GameId;
+
+    fn key(&self) -> &GameId {
+        &self.id
     }
 }
 
@@ -128,7 +151,7 @@ impl GameId {
     /// Get info about this game from the API.
     ///
     /// <https://dev.twitch.tv/docs/api/reference#get-games>
-    pub async fn get(&self, client: &Client<'_>) -> Result<Game, Error> {
+    pub async fn get<A: Auth>(&self, client: &Client<A>) -> Result<Game, Error> {
         Ok(
             client.get_query::<_, _, _, _, Vec<_>>("/games", &[("id", self)]).await?
             .into_iter()
@@ -178,8 +201,8 @@ impl VideoId {
     /// Get the next chunk of chatlog for this video.
     ///
     /// This uses an undocumented endpoint on the old Kraken API since no equivalent functionality seems to exist in the Helix API yet.
-    pub async fn chatlog_after_timestamp(&self, client: &Client<'_>, start: Duration) -> Result<Chatlog, Error> {
-        client.get_raw(&format!("https://api.twitch.tv/v5/videos/{}/comments", self), vec![("content_offset_seconds", format!("{}", start.num_seconds()))]).await
+    pub async fn chatlog_after_timestamp<A: Auth>(&self, client: &Client<A>, start: Duration) -> Result<Chatlog, Error> {
+        client.get_raw(&client.kraken_url(format_args!("/videos/{}/comments", self)), vec![("content_offset_seconds", format!("{}", start.num_seconds()))]).await
     }
 }
 
@@ -219,17 +242,19 @@ pub struct Stream {
 impl Stream {
     /// <https://dev.twitch.tv/docs/api/reference#get-streams>
     ///
-    /// Returns a list of all streams by decreasing viewer count. The optional parameters can be used to filter down the results. `games` is limited to 10 games, and the other two are limited to 100 elements.
-    pub fn list<'a>(client: &'a Client<'a>, games: Option<HashSet<GameId>>, users: Option<HashSet<UserId>>, languages: Option<HashSet<String>>) -> impl futures::Stream<Item = Result<Stream, Error>> + 'a {
-        let mut query = Vec::default();
-        if let Some(games) = games { query.extend(games.into_iter().map(|game_id| (format!("game_id"), game_id.0))); }
-        if let Some(users) = users { query.extend(users.into_iter().map(|user_id| (format!("user_id"), user_id.0))); }
-        if let Some(languages) = languages { query.extend(languages.into_iter().map(|lang_id| (format!("language"), lang_id))); }
-        paginated::stream(client, format!("{}/streams", HELIX_BASE_URL), query)
+    /// Returns a list of all streams by decreasing viewer count. The optional parameters can be used to filter down the results.
+    ///
+    /// The endpoint accepts up to 10 games and up to 100 users or languages per request. Larger sets are requested in batches, and the results are merged so the viewer count order is preserved. Note that merging requires a page from every batch before the first stream can be returned.
+    pub fn list<A: Auth>(client: &Client<A>, games: Option<HashSet<GameId>>, users: Option<HashSet<UserId>>, languages: Option<HashSet<String>>) -> Paginated<Stream> {
+        Paginated::new(client, client.helix_url("/streams"), Vec::default())
+            .batched("game_id", games.into_iter().flatten().map(|game_id| game_id.0), 10)
+            .batched("user_id", users.into_iter().flatten().map(|user_id| user_id.0), 100)
+            .batched("language", languages.into_iter().flatten(), 100)
+            .merge_by(|a, b| b.viewer_count.cmp(&a.viewer_count))
     }
 
     /// Convenience method to get the `Game` being streamed.
-    pub async fn game(&self, client: &Client<'_>) -> Result<Game, Error> {
+    pub async fn game<A: Auth>(&self, client: &Client<A>) -> Result<Game, Error> {
         self.game_id.get(client).await
     }
 
@@ -239,6 +264,14 @@ impl Stream {
     }
 }
 
+impl Keyed for Stream {
+    type Key = StreamId;
+
+    fn key(&self) -> &StreamId {
+        &self.id
+    }
+}
+
 impl fmt::Display for Stream {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.title.fmt(f)
@@ -289,23 +322,25 @@ pub struct User {
 impl User {
     /// <https://dev.twitch.tv/docs/api/reference#get-users>
     ///
-    /// Returns the users with the given login names in arbitrary order. A maximum of 100 login names may be given.
-    pub fn by_names<'a>(client: &'a Client<'a>, names: HashSet<String>) -> impl futures::Stream<Item = Result<User, Error>> + 'a {
-        paginated::stream(client, format!("{}/users", HELIX_BASE_URL), names.into_iter().map(|name| (format!("login"), name)).collect())
+    /// Returns the users with the given login names in arbitrary order. If more than 100 login names are given, they are requested in batches.
+    pub fn by_names<A: Auth>(client: &Client<A>, names: HashSet<String>) -> Paginated<User> {
+        Paginated::new(client, client.helix_url("/users"), Vec::default())
+            .batched("login", names, 100)
     }
 
     /// <https://dev.twitch.tv/docs/api/reference#get-users>
     ///
-    /// Returns the users with the given IDs in arbitrary order. A maximum of 100 user IDs may be given.
-    pub fn list<'a>(client: &'a Client<'a>, ids: HashSet<UserId>) -> impl futures::Stream<Item = Result<User, Error>> + 'a {
-        paginated::stream(client, format!("{}/users", HELIX_BASE_URL), ids.into_iter().map(|user_id| (format!("id"), user_id.0)).collect())
+    /// Returns the users with the given IDs in arbitrary order. If more than 100 user IDs are given, they are requested in batches.
+    pub fn list<A: Auth>(client: &Client<A>, ids: HashSet<UserId>) -> Paginated<User> {
+        Paginated::new(client, client.helix_url("/users"), Vec::default())
+            .batched("id", ids.into_iter().map(|user_id| user_id.0), 100)
     }
 
     /// <https://dev.twitch.tv/docs/api/reference#get-users>
     ///
     /// Returns the user the `client` is logged in as.
-    pub async fn me(client: &Client<'_>) -> Result<User, Error> {
-        let stream = paginated::stream(client, format!("{}/users", HELIX_BASE_URL), Vec::default());
+    pub async fn me(client: &Client<UserAuth>) -> Result<User, Error> {
+        let stream = Paginated::new(client, client.helix_url("/users"), Vec::default());
         pin_mut!(stream);
         let me = stream.try_next().await?.ok_or(Error::ExactlyOne(true))?;
         if stream.try_next().await?.is_some() {
@@ -315,3 +350,11 @@ impl User {
         }
     }
 }
+
+impl Keyed for User {
+    type Key = UserId;
+
+    fn key(&self) -> &UserId {
+        &self.id
+    }
+}
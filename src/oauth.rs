@@ -0,0 +1,507 @@
+//! Helpers for obtaining [user access tokens](https://dev.twitch.tv/docs/authentication/#user-access-tokens)
+
+use {
+    std::{
+        fmt,
+        str::FromStr,
+        time::{
+            Duration,
+            Instant,
+        },
+    },
+    itertools::Itertools as _,
+    rand::{
+        RngExt as _,
+        distr::Alphanumeric,
+    },
+    reqwest::Method,
+    serde::{
+        Deserialize,
+        Deserializer,
+        Serialize,
+        Serializer,
+        de::Error as _,
+    },
+    tokio::time::sleep,
+    url::Url,
+    crate::{
+        Auth,
+        Client,
+        Credentials,
+        Error,
+        ResponseExt as _,
+        UserAuth,
+        oidc,
+    },
+};
+
+#[derive(Deserialize)]
+struct UserTokenResponse {
+    access_token: String,
+    refresh_token: String,
+    /// Only included if the `openid` scope was requested.
+    id_token: Option<String>,
+}
+
+/// The [authorization code grant flow](https://dev.twitch.tv/docs/authentication/getting-tokens-oauth/#authorization-code-grant-flow), used to let users “Sign In With Twitch”.
+///
+/// 1. Construct an `AuthorizationCodeFlow` and redirect the user to its [`authorize_url`](AuthorizationCodeFlow::authorize_url). Remember its [`state`](AuthorizationCodeFlow::state), e.g. in the user's session.
+/// 2. Twitch redirects the user back to the redirect URI. Reconstruct the flow [`with_state`](AuthorizationCodeFlow::with_state) if necessary and pass the full redirect URL to [`handle_redirect`](AuthorizationCodeFlow::handle_redirect).
+/// 3. The resulting [`Credentials`] can be used with [`Client::new`].
+pub struct AuthorizationCodeFlow {
+    client: Client,
+    client_secret: String,
+    redirect_uri: Url,
+    scopes: String,
+    force_verify: bool,
+    state: String,
+    nonce: Option<String>,
+}
+
+impl AuthorizationCodeFlow {
+    /// Starts a new flow with a random `state` parameter.
+    ///
+    /// The `client` is used for its client ID and OAuth base URL, and to exchange the code. `redirect_uri` must exactly match one of the redirect URLs registered for the app.
+    pub fn new<A: Auth, U: fmt::Display, I: IntoIterator<Item = U>>(client: &Client<A>, client_secret: impl fmt::Display, redirect_uri: Url, scopes: I) -> AuthorizationCodeFlow {
+        AuthorizationCodeFlow {
+            client: client.cast(),
+            client_secret: client_secret.to_string(),
+            redirect_uri,
+            scopes: scopes.into_iter().join(" "),
+            force_verify: false,
+            state: rand::rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect(),
+            nonce: None,
+        }
+    }
+
+    /// Replaces the random `state` parameter, e.g. with one that was stored in the user's session when the flow was started.
+    pub fn with_state(mut self, state: impl fmt::Display) -> AuthorizationCodeFlow {
+        self.state = state.to_string();
+        self
+    }
+
+    /// Sets the `nonce` parameter, which is included in the [ID token](oidc) to protect against replay attacks. Only used if the `openid` scope is requested.
+    pub fn with_nonce(mut self, nonce: impl fmt::Display) -> AuthorizationCodeFlow {
+        self.nonce = Some(nonce.to_string());
+        self
+    }
+
+    /// If set to `true`, the user is asked to authorize the app even if they have done so before. This allows them to switch to a different Twitch account.
+    pub fn force_verify(mut self, force_verify: bool) -> AuthorizationCodeFlow {
+        self.force_verify = force_verify;
+        self
+    }
+
+    /// The value of the `state` parameter used to protect against [CSRF attacks](https://datatracker.ietf.org/doc/html/rfc6749#section-10.12).
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// The value of the `nonce` parameter, if one was set using [`with_nonce`](AuthorizationCodeFlow::with_nonce).
+    pub fn nonce(&self) -> Option<&str> {
+        self.nonce.as_deref()
+    }
+
+    /// The URL to which the user should be redirected to authorize the app.
+    pub fn authorize_url(&self) -> Url {
+        let mut url = Url::parse(&self.client.oauth_url("/authorize")).expect("OAuth base URL is a valid URL");
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client.inner.client_id)
+            .append_pair("force_verify", if self.force_verify { "true" } else { "false" })
+            .append_pair("redirect_uri", self.redirect_uri.as_str())
+            .append_pair("response_type", "code")
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", &self.state);
+        if let Some(nonce) = &self.nonce {
+            url.query_pairs_mut().append_pair("nonce", nonce);
+        }
+        url
+    }
+
+    /// Handles the request to the redirect URI, including the query string. Returns an error if the user denied the authorization request, the `state` parameter doesn't match, or the URL doesn't include an authorization code.
+    pub async fn handle_redirect(&self, redirect_url: &Url) -> Result<Credentials<UserAuth>, Error> {
+        let code = self.code_from_redirect(redirect_url)?;
+        let UserTokenResponse { access_token, refresh_token, .. } = self.request_tokens(&code).await?;
+        Ok(Credentials::from_user_tokens(access_token, refresh_token, &self.client_secret))
+    }
+
+    /// Like [`handle_redirect`](AuthorizationCodeFlow::handle_redirect), but also verifies the [ID token](oidc) included in the response and returns its claims. Requires the `openid` scope.
+    pub async fn handle_redirect_with_id_token(&self, redirect_url: &Url, keys: &oidc::KeySet) -> Result<(Credentials<UserAuth>, oidc::IdTokenClaims), Error> {
+        let code = self.code_from_redirect(redirect_url)?;
+        let UserTokenResponse { access_token, refresh_token, id_token } = self.request_tokens(&code).await?;
+        let claims = keys.verify(&self.client, &id_token.ok_or(oidc::IdTokenError::Missing)?, self.nonce.as_deref())?;
+        Ok((Credentials::from_user_tokens(access_token, refresh_token, &self.client_secret), claims))
+    }
+
+    /// Exchanges an authorization code for user credentials, after checking that the `state` returned by Twitch matches the one of this flow.
+    pub async fn exchange(&self, state: &str, code: &str) -> Result<Credentials<UserAuth>, Error> {
+        if state != self.state {
+            return Err(Error::OAuthStateMismatch)
+        }
+        let UserTokenResponse { access_token, refresh_token, .. } = self.request_tokens(code).await?;
+        Ok(Credentials::from_user_tokens(access_token, refresh_token, &self.client_secret))
+    }
+
+    fn code_from_redirect(&self, redirect_url: &Url) -> Result<String, Error> {
+        let mut code = None;
+        let mut state = None;
+        let mut error = None;
+        let mut error_description = None;
+        for (key, value) in redirect_url.query_pairs() {
+            match &*key {
+                "code" => code = Some(value.into_owned()),
+                "state" => state = Some(value.into_owned()),
+                "error" => error = Some(value.into_owned()),
+                "error_description" => error_description = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+        // check the state first, an attacker could also forge an error response
+        if state.as_deref() != Some(&*self.state) {
+            return Err(Error::OAuthStateMismatch)
+        }
+        if let Some(error) = error {
+            return Err(Error::OAuthDenied { error, description: error_description.unwrap_or_default() })
+        }
+        code.ok_or_else(|| Error::OAuthDenied {
+            error: "invalid_request".to_owned(),
+            description: "the redirect URL did not include an authorization code".to_owned(),
+        })
+    }
+
+    async fn request_tokens(&self, code: &str) -> Result<UserTokenResponse, Error> {
+        self.client.http_request(Method::POST, self.client.oauth_url("/token"))
+            .form(&[
+                ("client_id", &*self.client.inner.client_id),
+                ("client_secret", &self.client_secret),
+                ("code", code),
+                ("grant_type", "authorization_code"),
+                ("redirect_uri", self.redirect_uri.as_str()),
+            ])
+            .send().await?
+            .detailed_error_for_status().await?
+            .json_with_text_in_error().await
+    }
+}
+
+#[derive(Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    expires_in: u64,
+    interval: u64,
+    user_code: String,
+    verification_uri: Url,
+}
+
+/// The [device code grant flow](https://dev.twitch.tv/docs/authentication/getting-tokens-oauth/#device-code-grant-flow), for apps which can't open a browser, like CLI tools running on a server.
+///
+/// 1. Call [`DeviceCodeFlow::start`] and show the user the [`verification_uri`](DeviceCodeFlow::verification_uri) and [`user_code`](DeviceCodeFlow::user_code).
+/// 2. Call [`wait_for_authorization`](DeviceCodeFlow::wait_for_authorization), which resolves to [`Credentials`] once the user has authorized the app on another device.
+pub struct DeviceCodeFlow {
+    client: Client,
+    client_secret: Option<String>,
+    scopes: String,
+    device_code: String,
+    user_code: String,
+    verification_uri: Url,
+    expires_at: Instant,
+    interval: Duration,
+}
+
+impl DeviceCodeFlow {
+    /// Requests a device code from Twitch.
+    ///
+    /// The `client` is used for its client ID and OAuth base URL, and to poll for the token.
+    pub async fn start<A: Auth, U: fmt::Display, I: IntoIterator<Item = U>>(client: &Client<A>, scopes: I) -> Result<DeviceCodeFlow, Error> {
+        let scopes = scopes.into_iter().join(" ");
+        let DeviceCodeResponse { device_code, expires_in, interval, user_code, verification_uri } = client.http_request(Method::POST, client.oauth_url("/device"))
+            .form(&[
+                ("client_id", &*client.inner.client_id),
+                ("scopes", &scopes),
+            ])
+            .send().await?
+            .detailed_error_for_status().await?
+            .json_with_text_in_error().await?;
+        Ok(DeviceCodeFlow {
+            client: client.cast(),
+            client_secret: None,
+            expires_at: Instant::now() + Duration::from_secs(expires_in),
+            interval: Duration::from_secs(interval),
+            scopes, device_code, user_code, verification_uri,
+        })
+    }
+
+    /// Sets the client secret for apps registered as confidential clients. The resulting credentials will use it to refresh the token.
+    pub fn client_secret(mut self, client_secret: impl fmt::Display) -> DeviceCodeFlow {
+        self.client_secret = Some(client_secret.to_string());
+        self
+    }
+
+    /// The code the user has to enter at the [`verification_uri`](DeviceCodeFlow::verification_uri).
+    pub fn user_code(&self) -> &str {
+        &self.user_code
+    }
+
+    /// The URL where the user authorizes the app. It already includes the [`user_code`](DeviceCodeFlow::user_code) as a query parameter.
+    pub fn verification_uri(&self) -> &Url {
+        &self.verification_uri
+    }
+
+    /// Polls Twitch until the user has authorized the app, respecting the polling interval requested by Twitch.
+    ///
+    /// Returns [`Error::DeviceCodeExpired`] if the user didn't authorize the app in time, or [`Error::OAuthDenied`] if they declined.
+    pub async fn wait_for_authorization(mut self) -> Result<Credentials<UserAuth>, Error> {
+        loop {
+            if Instant::now() + self.interval >= self.expires_at {
+                return Err(Error::DeviceCodeExpired)
+            }
+            sleep(self.interval).await;
+            let response = self.client.http_request(Method::POST, self.client.oauth_url("/token"))
+                .form(&[
+                    ("client_id", &*self.client.inner.client_id),
+                    ("device_code", &self.device_code),
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                    ("scopes", &self.scopes),
+                ])
+                .send().await?
+                .detailed_error_for_status().await;
+            match response {
+                Ok(response) => {
+                    let UserTokenResponse { access_token, refresh_token, .. } = response.json_with_text_in_error().await?;
+                    return Ok(if let Some(client_secret) = self.client_secret {
+                        Credentials::from_user_tokens(access_token, refresh_token, client_secret)
+                    } else {
+                        Credentials::from_public_user_tokens(access_token, refresh_token)
+                    })
+                }
+                Err(Error::Api { message, .. }) if message == "authorization_pending" => {}
+                // as specified in RFC 8628, section 3.5
+                Err(Error::Api { message, .. }) if message == "slow_down" => self.interval += Duration::from_secs(5),
+                Err(Error::Api { message, .. }) if message == "invalid device code" => return Err(Error::DeviceCodeExpired),
+                Err(Error::Api { message, .. }) if message == "access_denied" => return Err(Error::OAuthDenied { error: message, description: String::default() }),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// An error returned when parsing an unknown [`Scope`].
+#[derive(Debug, thiserror::Error)]
+#[error("unknown Twitch scope: {0}")]
+pub struct UnknownScope(pub String);
+
+macro_rules! scopes {
+    ($($Variant:ident = $name:literal,)+) => {
+        /// An [OAuth scope](https://dev.twitch.tv/docs/authentication/scopes/), i.e. a permission which can be requested from a user.
+        ///
+        /// Can be passed to [`Credentials::from_client_secret`] and the OAuth flows in this module, since it implements `Display`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum Scope {
+            $(
+                #[doc = concat!("`", $name, "`")]
+                $Variant,
+            )+
+        }
+
+        impl Scope {
+            /// All known scopes.
+            pub const ALL: &'static [Scope] = &[$(Scope::$Variant,)+];
+
+            /// The name of this scope as used by the Twitch API.
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Scope::$Variant => $name,)+
+                }
+            }
+        }
+
+        impl FromStr for Scope {
+            type Err = UnknownScope;
+
+            fn from_str(s: &str) -> Result<Scope, UnknownScope> {
+                match s {
+                    $($name => Ok(Scope::$Variant),)+
+                    _ => Err(UnknownScope(s.to_owned())),
+                }
+            }
+        }
+    };
+}
+
+scopes! {
+    AnalyticsReadExtensions = "analytics:read:extensions",
+    AnalyticsReadGames = "analytics:read:games",
+    BitsRead = "bits:read",
+    ChannelBot = "channel:bot",
+    ChannelEditCommercial = "channel:edit:commercial",
+    ChannelManageAds = "channel:manage:ads",
+    ChannelManageBroadcast = "channel:manage:broadcast",
+    ChannelManageExtensions = "channel:manage:extensions",
+    ChannelManageGuestStar = "channel:manage:guest_star",
+    ChannelManageModerators = "channel:manage:moderators",
+    ChannelManagePolls = "channel:manage:polls",
+    ChannelManagePredictions = "channel:manage:predictions",
+    ChannelManageRaids = "channel:manage:raids",
+    ChannelManageRedemptions = "channel:manage:redemptions",
+    ChannelManageSchedule = "channel:manage:schedule",
+    ChannelManageVideos = "channel:manage:videos",
+    ChannelManageVips = "channel:manage:vips",
+    ChannelModerate = "channel:moderate",
+    ChannelReadAds = "channel:read:ads",
+    ChannelReadCharity = "channel:read:charity",
+    ChannelReadEditors = "channel:read:editors",
+    ChannelReadGoals = "channel:read:goals",
+    ChannelReadGuestStar = "channel:read:guest_star",
+    ChannelReadHypeTrain = "channel:read:hype_train",
+    ChannelReadPolls = "channel:read:polls",
+    ChannelReadPredictions = "channel:read:predictions",
+    ChannelReadRedemptions = "channel:read:redemptions",
+    ChannelReadStreamKey = "channel:read:stream_key",
+    ChannelReadSubscriptions = "channel:read:subscriptions",
+    ChannelReadVips = "channel:read:vips",
+    ChatEdit = "chat:edit",
+    ChatRead = "chat:read",
+    ClipsEdit = "clips:edit",
+    ModerationRead = "moderation:read",
+    ModeratorManageAnnouncements = "moderator:manage:announcements",
+    ModeratorManageAutomod = "moderator:manage:automod",
+    ModeratorManageAutomodSettings = "moderator:manage:automod_settings",
+    ModeratorManageBannedUsers = "moderator:manage:banned_users",
+    ModeratorManageBlockedTerms = "moderator:manage:blocked_terms",
+    ModeratorManageChatMessages = "moderator:manage:chat_messages",
+    ModeratorManageChatSettings = "moderator:manage:chat_settings",
+    ModeratorManageGuestStar = "moderator:manage:guest_star",
+    ModeratorManageShieldMode = "moderator:manage:shield_mode",
+    ModeratorManageShoutouts = "moderator:manage:shoutouts",
+    ModeratorManageUnbanRequests = "moderator:manage:unban_requests",
+    ModeratorManageWarnings = "moderator:manage:warnings",
+    ModeratorReadAutomodSettings = "moderator:read:automod_settings",
+    ModeratorReadBannedUsers = "moderator:read:banned_users",
+    ModeratorReadBlockedTerms = "moderator:read:blocked_terms",
+    ModeratorReadChatMessages = "moderator:read:chat_messages",
+    ModeratorReadChatSettings = "moderator:read:chat_settings",
+    ModeratorReadChatters = "moderator:read:chatters",
+    ModeratorReadFollowers = "moderator:read:followers",
+    ModeratorReadGuestStar = "moderator:read:guest_star",
+    ModeratorReadModerators = "moderator:read:moderators",
+    ModeratorReadShieldMode = "moderator:read:shield_mode",
+    ModeratorReadShoutouts = "moderator:read:shoutouts",
+    ModeratorReadSuspiciousUsers = "moderator:read:suspicious_users",
+    ModeratorReadUnbanRequests = "moderator:read:unban_requests",
+    ModeratorReadVips = "moderator:read:vips",
+    ModeratorReadWarnings = "moderator:read:warnings",
+    OpenId = "openid",
+    UserBot = "user:bot",
+    UserEdit = "user:edit",
+    UserEditBroadcast = "user:edit:broadcast",
+    UserManageBlockedUsers = "user:manage:blocked_users",
+    UserManageChatColor = "user:manage:chat_color",
+    UserManageWhispers = "user:manage:whispers",
+    UserReadBlockedUsers = "user:read:blocked_users",
+    UserReadBroadcast = "user:read:broadcast",
+    UserReadChat = "user:read:chat",
+    UserReadEmail = "user:read:email",
+    UserReadEmotes = "user:read:emotes",
+    UserReadFollows = "user:read:follows",
+    UserReadModeratedChannels = "user:read:moderated_channels",
+    UserReadSubscriptions = "user:read:subscriptions",
+    UserReadWhispers = "user:read:whispers",
+    UserWriteChat = "user:write:chat",
+    WhispersEdit = "whispers:edit",
+    WhispersRead = "whispers:read",
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl Serialize for Scope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Scope {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Scope, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        url::Url,
+        crate::{
+            Client,
+            Credentials,
+            Error,
+        },
+        super::{
+            AuthorizationCodeFlow,
+            Scope,
+            UnknownScope,
+        },
+    };
+
+    fn flow() -> AuthorizationCodeFlow {
+        let client = Client::new("twitch_helix tests", "test-client-id", Credentials::from_oauth_token("unused")).expect("failed to build client");
+        AuthorizationCodeFlow::new(&client, "test-secret", Url::parse("http://localhost/callback").expect("invalid URL"), [Scope::UserReadFollows])
+            .with_state("test-state")
+    }
+
+    fn code_from_redirect(query: &str) -> Result<String, Error> {
+        flow().code_from_redirect(&Url::parse(&format!("http://localhost/callback?{query}")).expect("invalid URL"))
+    }
+
+    #[test]
+    fn redirect_with_code() {
+        assert_eq!(code_from_redirect("code=test-code&scope=user%3Aread%3Afollows&state=test-state").expect("valid redirect was rejected"), "test-code");
+    }
+
+    #[test]
+    fn redirect_state_mismatch() {
+        assert!(matches!(code_from_redirect("code=test-code&state=another-state"), Err(Error::OAuthStateMismatch)));
+        assert!(matches!(code_from_redirect("code=test-code"), Err(Error::OAuthStateMismatch)));
+    }
+
+    #[test]
+    fn redirect_error_with_wrong_state() {
+        assert!(matches!(code_from_redirect("error=access_denied&error_description=The+user+denied+you+access&state=another-state"), Err(Error::OAuthStateMismatch)));
+    }
+
+    #[test]
+    fn redirect_error() {
+        match code_from_redirect("error=access_denied&error_description=The+user+denied+you+access&state=test-state") {
+            Err(Error::OAuthDenied { error, description }) => {
+                assert_eq!(error, "access_denied");
+                assert_eq!(description, "The user denied you access");
+            }
+            result => panic!("expected OAuthDenied, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn redirect_without_code() {
+        assert!(matches!(code_from_redirect("state=test-state"), Err(Error::OAuthDenied { error, .. }) if error == "invalid_request"));
+    }
+
+    #[test]
+    fn scopes_round_trip() {
+        for &scope in Scope::ALL {
+            assert_eq!(scope.as_str().parse::<Scope>().ok(), Some(scope));
+            assert_eq!(scope.to_string(), scope.as_str());
+            let json = serde_json::to_string(&scope).expect("failed to serialize scope");
+            assert_eq!(json, format!("\"{}\"", scope.as_str()));
+            assert_eq!(serde_json::from_str::<Scope>(&json).expect("failed to deserialize scope"), scope);
+        }
+    }
+
+    #[test]
+    fn unknown_scope() {
+        let UnknownScope(name) = "user:read:nonexistent".parse::<Scope>().expect_err("parsed unknown scope");
+        assert_eq!(name, "user:read:nonexistent");
+        assert!(serde_json::from_str::<Scope>("\"user:read:nonexistent\"").is_err());
+    }
+}
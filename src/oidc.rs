@@ -0,0 +1,259 @@
+//! [OpenID Connect](https://dev.twitch.tv/docs/authentication/getting-tokens-oidc/) support for “Sign In With Twitch”
+//!
+//! Request the [`openid`](crate::oauth::Scope::OpenId) scope and use [`AuthorizationCodeFlow::handle_redirect_with_id_token`](crate::oauth::AuthorizationCodeFlow::handle_redirect_with_id_token) to get the verified identity of the user along with their credentials.
+
+use {
+    chrono::prelude::*,
+    itertools::Itertools as _,
+    jsonwebtoken::{
+        Algorithm,
+        DecodingKey,
+        Validation,
+        jwk::JwkSet,
+    },
+    reqwest::Method,
+    serde::Deserialize,
+    url::Url,
+    crate::{
+        Auth,
+        Client,
+        Error,
+        ResponseExt as _,
+        UserAuth,
+        model::UserId,
+        oauth::Scope,
+    },
+};
+
+/// An error which occurred while verifying an ID token.
+#[derive(Debug, thiserror::Error)]
+pub enum IdTokenError {
+    /// The token is malformed, its signature is invalid, or one of its claims failed validation.
+    #[error(transparent)] Jwt(#[from] jsonwebtoken::errors::Error),
+    /// The token response didn't include an ID token, probably because the `openid` scope wasn't requested.
+    #[error("the token response did not include an ID token")]
+    Missing,
+    /// The `nonce` claim didn't match the nonce sent in the authorization request.
+    #[error("ID token nonce mismatch")]
+    NonceMismatch,
+    /// The token was signed with a key that's not in the [`KeySet`].
+    #[error("ID token was signed with an unknown key")]
+    UnknownKey,
+}
+
+/// The public keys used to sign ID tokens, see <https://dev.twitch.tv/docs/authentication/getting-tokens-oidc/#verifying-the-id-token>
+///
+/// Twitch rarely rotates its keys, so a key set should be fetched once and reused. For offline tests, a key set can be deserialized from a JWKS document instead.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct KeySet(JwkSet);
+
+impl KeySet {
+    /// Fetches the current key set from Twitch, using the client's OAuth base URL.
+    pub async fn fetch<A: Auth>(client: &Client<A>) -> Result<KeySet, Error> {
+        client.http_request(Method::GET, client.oauth_url("/keys"))
+            .send().await?
+            .detailed_error_for_status().await?
+            .json_with_text_in_error().await
+    }
+
+    /// Verifies the signature of an ID token and checks its issuer, audience (the client ID of the `client`), and expiry.
+    ///
+    /// If a `nonce` was sent in the authorization request, it must be passed here and is compared to the token's `nonce` claim.
+    pub fn verify<A: Auth>(&self, client: &Client<A>, id_token: &str, nonce: Option<&str>) -> Result<IdTokenClaims, IdTokenError> {
+        let header = jsonwebtoken::decode_header(id_token)?;
+        let jwk = match &header.kid {
+            Some(kid) => self.0.find(kid),
+            None => self.0.keys.iter().exactly_one().ok(),
+        }.ok_or(IdTokenError::UnknownKey)?;
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[client.oauth_url("")]);
+        validation.set_audience(&[&client.inner.client_id]);
+        validation.set_required_spec_claims(&["aud", "exp", "iss", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+        if let Some(nonce) = nonce {
+            if claims.nonce.as_deref() != Some(nonce) {
+                return Err(IdTokenError::NonceMismatch)
+            }
+        }
+        Ok(claims)
+    }
+}
+
+impl From<JwkSet> for KeySet {
+    fn from(keys: JwkSet) -> KeySet {
+        KeySet(keys)
+    }
+}
+
+/// The claims of a verified ID token.
+///
+/// The optional claims are only included if they were requested using the `claims` parameter of the authorization request.
+#[derive(Debug, Clone, Deserialize)]
+#[allow(missing_docs)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    /// The ID of the user who signed in.
+    pub sub: UserId,
+    /// The client ID of the app.
+    pub aud: String,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub exp: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub iat: DateTime<Utc>,
+    pub nonce: Option<String>,
+    pub azp: Option<String>,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub picture: Option<Url>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Information about the user who authorized a token, as returned by <https://dev.twitch.tv/docs/authentication/getting-tokens-oidc/#getting-claims-information-from-an-access-token>
+///
+/// The optional fields are only included if they were requested using the `claims` parameter of the authorization request.
+#[derive(Debug, Clone, Deserialize)]
+#[allow(missing_docs)]
+pub struct UserInfo {
+    pub sub: UserId,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub picture: Option<Url>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl UserInfo {
+    /// Returns information about the user the `client` is logged in as.
+    ///
+    /// Requires a user access token that includes the [`openid`](Scope::OpenId) scope.
+    pub async fn get(client: &Client<UserAuth>) -> Result<UserInfo, Error> {
+        async fn request(client: &Client<UserAuth>, token: &str) -> Result<UserInfo, Error> {
+            // not a Helix endpoint, so it doesn't count towards the Helix rate limit
+            client.http_request(Method::GET, client.oauth_url("/userinfo"))
+                .bearer_auth(token)
+                .send().await?
+                .detailed_error_for_status().await?
+                .json_with_text_in_error().await
+        }
+
+        client.require_scope(Scope::OpenId).await?;
+        let token = client.get_oauth_token(None).await?;
+        match request(client, &token).await {
+            // reauthenticate once, like other requests
+            Err(e) if e.is_invalid_oauth_token() => request(client, &client.oauth_token(Some(e), Some(&token)).await?).await,
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        chrono::{
+            TimeDelta,
+            prelude::*,
+        },
+        jsonwebtoken::{
+            Algorithm,
+            EncodingKey,
+            Header,
+            errors::ErrorKind,
+        },
+        serde_json::json,
+        crate::{
+            Client,
+            Credentials,
+            model::UserId,
+        },
+        super::{
+            IdTokenClaims,
+            IdTokenError,
+            KeySet,
+        },
+    };
+
+    /// The private key matching [`KEY_SET`], in PKCS#1 DER format. Only used for these tests.
+    const PRIVATE_KEY: &[u8] = include_bytes!("../tests/data/oidc-test-key.der");
+    const KEY_SET: &str = r#"{"keys": [{"kty": "RSA", "alg": "RS256", "use": "sig", "kid": "test", "e": "AQAB", "n": "mr-YLOdkaT2XXzjxS9Xa-bBrAN4S6xyGnnv6OfJ7la81MxGoBpRm4ENL-yEwrCNd2mxmjiEBjxIHRjGcnPAm02M_70ViTlVnpu1nN0U6KWRHy3CsjiWBO9f0ouVOQpQPk45rVEQbIRY0otGVllkeV0W6_eUElkFSueFgJubYfeynsat7Q9KAToON56HGeXilUaDua-bCbgoeBjPA4WLjwyfRtdb2vpXoVyt1nz6zoflOF_kvmBEq8gcJ8rb6SEUXqJqY1MhI2GEVqOU8asq7gShqLPyMo3_0087khxPOmPBL03sH67M2z1ab3SH_n2BUq2oZUN6Dc47dlmW6vJerdw"}]}"#;
+    const ISSUER: &str = "https://id.twitch.tv/oauth2";
+    const CLIENT_ID: &str = "test-client-id";
+
+    fn sign(kid: &str, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_owned());
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_rsa_der(PRIVATE_KEY)).expect("failed to sign test token")
+    }
+
+    fn claims() -> serde_json::Value {
+        let now = Utc::now();
+        json!({
+            "iss": ISSUER,
+            "sub": "12345",
+            "aud": CLIENT_ID,
+            "exp": (now + TimeDelta::hours(1)).timestamp(),
+            "iat": now.timestamp(),
+            "nonce": "test-nonce",
+        })
+    }
+
+    fn verify(id_token: &str, nonce: Option<&str>) -> Result<IdTokenClaims, IdTokenError> {
+        let client = Client::new("twitch_helix tests", CLIENT_ID, Credentials::from_oauth_token("unused")).expect("failed to build client");
+        let keys = serde_json::from_str::<KeySet>(KEY_SET).expect("failed to parse key set");
+        keys.verify(&client, id_token, nonce)
+    }
+
+    fn assert_jwt_error(result: Result<IdTokenClaims, IdTokenError>, expected: ErrorKind) {
+        match result {
+            Err(IdTokenError::Jwt(e)) => assert_eq!(*e.kind(), expected),
+            Err(e) => panic!("expected {expected:?}, got {e:?}"),
+            Ok(_) => panic!("expected {expected:?}, but the token was accepted"),
+        }
+    }
+
+    #[test]
+    fn valid_token() {
+        let claims = verify(&sign("test", claims()), Some("test-nonce")).expect("valid token was rejected");
+        assert_eq!(claims.sub, UserId("12345".to_owned()));
+        assert_eq!(claims.aud, CLIENT_ID);
+    }
+
+    #[test]
+    fn wrong_issuer() {
+        let mut claims = claims();
+        claims["iss"] = json!("https://example.com/oauth2");
+        assert_jwt_error(verify(&sign("test", claims), Some("test-nonce")), ErrorKind::InvalidIssuer);
+    }
+
+    #[test]
+    fn wrong_audience() {
+        let mut claims = claims();
+        claims["aud"] = json!("another-client-id");
+        assert_jwt_error(verify(&sign("test", claims), Some("test-nonce")), ErrorKind::InvalidAudience);
+    }
+
+    #[test]
+    fn expired() {
+        let mut claims = claims();
+        claims["exp"] = json!((Utc::now() - TimeDelta::hours(1)).timestamp());
+        assert_jwt_error(verify(&sign("test", claims), Some("test-nonce")), ErrorKind::ExpiredSignature);
+    }
+
+    #[test]
+    fn nonce_mismatch() {
+        assert!(matches!(verify(&sign("test", claims()), Some("another-nonce")), Err(IdTokenError::NonceMismatch)));
+    }
+
+    #[test]
+    fn missing_nonce() {
+        let mut claims = claims();
+        claims.as_object_mut().expect("claims are an object").remove("nonce");
+        assert!(matches!(verify(&sign("test", claims), Some("test-nonce")), Err(IdTokenError::NonceMismatch)));
+    }
+
+    #[test]
+    fn unknown_key() {
+        assert!(matches!(verify(&sign("another-key", claims()), Some("test-nonce")), Err(IdTokenError::UnknownKey)));
+    }
+}
@@ -1,53 +1,114 @@
 //! A data structure for working with paginated endpoints
 
 use {
-    std::vec,
-    futures::TryStreamExt as _,
+    std::{
+        cmp,
+        collections::HashSet,
+        convert::Infallible as Never,
+        fmt,
+        hash::Hash,
+        pin::Pin,
+        str::FromStr,
+        sync::{
+            Arc,
+            atomic::{
+                self,
+                AtomicUsize,
+            },
+        },
+        task::{
+            Context,
+            Poll,
+        },
+        time::Instant,
+        vec,
+    },
+    futures::{
+        Stream,
+        StreamExt as _,
+        TryStreamExt as _,
+        future,
+        stream::BoxStream,
+    },
+    itertools::Itertools as _,
+    reqwest::Method,
     serde::{
         Deserialize,
+        Serialize,
         de::DeserializeOwned,
     },
+    tokio::{
+        sync::{
+            Semaphore,
+            mpsc,
+        },
+        task::AbortHandle,
+        time::sleep,
+    },
     crate::{
+        Auth,
         Client,
         Error,
+        oauth::Scope,
     },
 };
 
-#[derive(Deserialize)]
-#[serde(from = "Option<String>")]
-enum Cursor {
+/// An opaque position in the results of a paginated endpoint, as returned by Twitch.
+///
+/// Cursors can be serialized and saved, e.g. to [resume](Paginated::resume_from) a long crawl after a restart. Twitch doesn't document how long a cursor remains valid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Cursor(String);
+
+impl FromStr for Cursor {
+    type Err = Never;
+
+    fn from_str(s: &str) -> Result<Cursor, Never> {
+        Ok(Cursor(s.to_owned()))
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Default, Clone, Deserialize)]
+#[serde(from = "Option<Cursor>")]
+enum Position {
     Start,
-    At(String),
+    At(Cursor),
+    #[default]
     End,
 }
 
-impl Cursor {
-    fn query(self) -> Option<Vec<(String, String)>> {
+impl Position {
+    fn query(self, direction: Direction) -> Option<Vec<(String, String)>> {
         match self {
-            Cursor::Start => Some(Vec::default()),
-            Cursor::At(cursor) => Some(vec![(format!("after"), cursor)]),
-            Cursor::End => None, // to break the loop
+            Position::Start => Some(Vec::default()),
+            Position::At(Cursor(cursor)) => Some(vec![(match direction {
+                Direction::Forward => "after",
+                Direction::Backward => "before",
+            }.to_owned(), cursor)]),
+            Position::End => None, // to break the loop
         }
     }
 }
 
-impl Default for Cursor {
-    fn default() -> Cursor { Cursor::End }
-}
-
-impl From<Option<String>> for Cursor {
-    fn from(cursor: Option<String>) -> Cursor {
+impl From<Option<Cursor>> for Position {
+    fn from(cursor: Option<Cursor>) -> Position {
         if let Some(cursor) = cursor {
-            Cursor::At(cursor)
+            Position::At(cursor)
         } else {
-            Cursor::End
+            Position::End
         }
     }
 }
 
 #[derive(Default, Deserialize)]
 struct PaginationInfo {
-    cursor: Cursor,
+    cursor: Position,
 }
 
 #[derive(Deserialize)]
@@ -57,23 +118,461 @@ struct PaginatedResult<T> {
     pagination: PaginationInfo,
 }
 
-pub(crate) fn stream<'a, T: DeserializeOwned>(client: &'a Client<'a>, uri: String, query: Vec<(String, String)>) -> impl futures::stream::Stream<Item = Result<T, Error>> + 'a {
-    futures::stream::try_unfold(Cursor::Start, move |cursor| {
+/// The direction in which a [`Paginated`] stream moves through the results.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Request the next page using the `after` cursor. This is the default and is supported by all paginated endpoints.
+    #[default]
+    Forward,
+    /// Request the next page using the `before` cursor. Only supported by some endpoints, see the Twitch API reference.
+    ///
+    /// There is nothing before the first page, so this must be combined with [`Paginated::resume_from`] to start at a cursor returned by an earlier forward pass.
+    Backward,
+}
+
+/// An item with a unique ID, which allows [deduplicating](Paginated::deduplicate) paginated results.
+pub trait Keyed {
+    /// The type of the ID, e.g. [`StreamId`](crate::model::StreamId).
+    type Key: Eq + Hash + Clone + Send + 'static;
+
+    /// Returns the ID of this item.
+    fn key(&self) -> &Self::Key;
+}
+
+type Filter<T> = Box<dyn FnMut(&T) -> bool + Send>;
+
+/// A page of results, as returned by [`Paginated::pages`].
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    /// The items on this page.
+    pub items: Vec<T>,
+    /// The cursor pointing past this page, which can be passed to [`Paginated::resume_from`] to continue with the next page. `None` if this is the last page, or if the request was split into multiple batches.
+    pub cursor: Option<Cursor>,
+    /// The number of items which were removed from this page because they had already been returned, see [`Paginated::deduplicate`].
+    pub dropped: usize,
+}
+
+/// A stream of all items returned by a paginated endpoint.
+///
+/// No requests are sent until the stream is first polled, so it can be configured using the methods on this type before that.
+///
+/// Some endpoints limit how many IDs can be passed in a single request. Methods like [`User::list`](crate::model::User::list) split larger sets of IDs into batches which are requested concurrently and merged into this stream.
+pub struct Paginated<T> {
+    client: Client,
+    uri: String,
+    query: Vec<(String, String)>,
+    /// Additional query parameters for each batch. Always contains at least one (possibly empty) batch.
+    batches: Vec<Vec<(String, String)>>,
+    merge_order: Option<fn(&T, &T) -> cmp::Ordering>,
+    max_concurrent_requests: usize,
+    page_size: Option<usize>,
+    direction: Direction,
+    start: Option<Cursor>,
+    resilient: bool,
+    prefetch: usize,
+    filter: Option<Filter<T>>,
+    dropped: Arc<AtomicUsize>,
+    required_scope: Option<Scope>,
+    stream: Option<BoxStream<'static, Result<T, Error>>>,
+}
+
+impl<T: DeserializeOwned + Send + 'static> Paginated<T> {
+    pub(crate) fn new<A: Auth>(client: &Client<A>, uri: String, query: Vec<(String, String)>) -> Paginated<T> {
+        Paginated {
+            client: client.cast(),
+            batches: vec![Vec::default()],
+            merge_order: None,
+            max_concurrent_requests: 4,
+            page_size: None,
+            direction: Direction::default(),
+            start: None,
+            resilient: false,
+            prefetch: 0,
+            filter: None,
+            dropped: Arc::default(),
+            required_scope: None,
+            stream: None,
+            uri, query,
+        }
+    }
+
+    /// Adds a query parameter with the given values, split into batches of at most `batch_size` values each. If multiple parameters are batched, each combination of batches is requested.
+    pub(crate) fn batched(mut self, key: &str, values: impl IntoIterator<Item = String>, batch_size: usize) -> Paginated<T> {
+        let values = values.into_iter().map(|value| (key.to_owned(), value)).collect_vec();
+        if !values.is_empty() {
+            self.batches = self.batches.iter()
+                .flat_map(|batch| values.chunks(batch_size).map(move |chunk| batch.iter().chain(chunk).cloned().collect()))
+                .collect();
+        }
+        self
+    }
+
+    /// If the request is split into multiple batches, merges their items according to the given order, assuming that each batch is already sorted that way. Otherwise, items are returned in the order the pages arrive.
+    pub(crate) fn merge_by(mut self, order: fn(&T, &T) -> cmp::Ordering) -> Paginated<T> {
+        self.merge_order = Some(order);
+        self
+    }
+
+    /// Checks that the token has the given scope before requesting the first page, see [`Error::MissingScope`].
+    pub(crate) fn require_scope(mut self, scope: Scope) -> Paginated<T> {
+        self.required_scope = Some(scope);
+        self
+    }
+
+    /// Sets the number of items requested per page, using the `first` parameter. The default depends on the endpoint and is usually 20. Values are clamped to the range from 1 to 100, the maximum accepted by Helix; some endpoints have a lower maximum.
+    ///
+    /// Larger pages mean fewer requests and therefore fewer rate limit points used for the same number of items.
+    pub fn page_size(mut self, page_size: usize) -> Paginated<T> {
+        self.page_size = Some(page_size.clamp(1, 100));
+        self
+    }
+
+    /// Sets the direction in which to paginate. Defaults to [`Direction::Forward`]. [`Direction::Backward`] requires a starting cursor set using [`Paginated::resume_from`].
+    pub fn direction(mut self, direction: Direction) -> Paginated<T> {
+        self.direction = direction;
+        self
+    }
+
+    /// Starts at the given cursor instead of the beginning of the results, e.g. to resume a crawl using the cursor of the last [`Page`] that was processed.
+    ///
+    /// This has no effect if the request is split into multiple batches.
+    pub fn resume_from(mut self, cursor: Cursor) -> Paginated<T> {
+        self.start = Some(cursor);
+        self
+    }
+
+    /// If set to `true`, a page request which failed is retried with the same cursor according to the client's [`RetryPolicy`](crate::RetryPolicy), and once the policy gives up, the error is returned as [`Error::Pagination`], which includes the cursor of the failed page. This allows resuming a long crawl after an outage using [`Paginated::resume_from`]. Defaults to `false`.
+    ///
+    /// The policy is applied once per page, in place of the retries the client normally performs for each request.
+    pub fn resilient(mut self, resilient: bool) -> Paginated<T> {
+        self.resilient = resilient;
+        self
+    }
+
+    /// Requests up to the given number of pages ahead of the consumer, so the next page is already in flight while the current one is being processed. Defaults to 0, which means each page is only requested once the previous one has been consumed.
+    ///
+    /// Prefetched pages are buffered in memory. Once the buffer is full, no further pages are requested until the consumer catches up. If the request is split into multiple batches, each batch has its own buffer.
+    ///
+    /// # Panics
+    ///
+    /// If prefetching is enabled, the stream panics if it's first polled outside of a Tokio runtime, since the pages are requested by a separate task.
+    pub fn prefetch(mut self, pages: usize) -> Paginated<T> {
+        self.prefetch = pages;
+        self
+    }
+
+    /// Sets how many requests may be in flight at the same time if the request is split into multiple batches. Defaults to 4.
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Paginated<T> {
+        self.max_concurrent_requests = max_concurrent_requests.max(1);
+        self
+    }
+
+    /// Returns a stream of entire pages instead of individual items. Each page includes the cursor pointing past it, which can be saved to resume later.
+    ///
+    /// If the request is split into multiple batches, pages are returned in the order they arrive.
+    pub fn pages(mut self) -> impl Stream<Item = Result<Page<T>, Error>> + Send + 'static {
+        self.page_stream()
+    }
+
+    /// The total number of items which were skipped so far because they had already been returned, see [`Paginated::deduplicate`].
+    ///
+    /// To read this after consuming the stream, consume it by reference, e.g. `(&mut stream).try_collect()`.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Returns a function which creates one stream of pages per batch. Creating the streams may spawn [prefetch](Paginated::prefetch) tasks, so this is deferred until after the scope check.
+    fn batch_streams(&self) -> impl FnOnce() -> Vec<BoxStream<'static, Result<Page<T>, Error>>> + Send + 'static {
+        let mut query = self.query.clone();
+        if let Some(page_size) = self.page_size {
+            query.push(("first".to_owned(), page_size.to_string()));
+        }
+        let (start, semaphore) = if let [_] = &*self.batches {
+            (self.start.clone().map_or(Position::Start, Position::At), None)
+        } else {
+            (Position::Start, Some(Arc::new(Semaphore::new(self.max_concurrent_requests))))
+        };
+        let client = self.client.clone();
+        let uri = self.uri.clone();
+        let batches = self.batches.clone();
+        let direction = self.direction;
+        let resilient = self.resilient;
+        let prefetch_pages = self.prefetch;
+        move || batches.into_iter().map(|batch| {
+            let query = query.iter().chain(&batch).cloned().collect();
+            let mut stream = pages(client.clone(), uri.clone(), query, direction, start.clone(), resilient, semaphore.clone()).boxed();
+            if semaphore.is_some() {
+                // a cursor only applies to the batch it came from, so it can't be used to resume the combined stream
+                stream = stream
+                    .map_ok(|page| Page { cursor: None, ..page })
+                    .map_err(|e| match e {
+                        Error::Pagination { source, .. } => Error::Pagination { cursor: None, source },
+                        e => e,
+                    })
+                    .boxed();
+            }
+            if prefetch_pages > 0 { prefetch(stream, prefetch_pages) } else { stream }
+        }).collect()
+    }
+
+    /// Creates the stream using `make` once the token is known to have the [required scope](Paginated::require_scope), so that no requests are sent to the endpoint otherwise.
+    fn with_scope_check<I: Send + 'static>(&self, make: impl FnOnce() -> BoxStream<'static, Result<I, Error>> + Send + 'static) -> BoxStream<'static, Result<I, Error>> {
+        let Some(scope) = self.required_scope else { return make() };
+        let client = self.client.clone();
+        futures::stream::once(async move {
+            client.require_scope(scope).await?;
+            Ok::<_, Error>(make())
+        }).try_flatten().boxed()
+    }
+
+    fn page_stream(&mut self) -> BoxStream<'static, Result<Page<T>, Error>> {
+        let batch_streams = self.batch_streams();
+        let stream = self.with_scope_check(move || {
+            let mut batches = batch_streams();
+            if batches.len() == 1 {
+                batches.pop().expect("just checked")
+            } else {
+                futures::stream::select_all(batches).boxed()
+            }
+        });
+        if let Some(mut filter) = self.filter.take() {
+            let dropped = Arc::clone(&self.dropped);
+            stream.map_ok(move |mut page| {
+                let len = page.items.len();
+                page.items.retain(&mut filter);
+                page.dropped = len - page.items.len();
+                dropped.fetch_add(page.dropped, atomic::Ordering::Relaxed);
+                page
+            }).boxed()
+        } else {
+            stream
+        }
+    }
+
+    fn item_stream(&mut self) -> BoxStream<'static, Result<T, Error>> {
+        let Some(order) = self.merge_order.filter(|_| self.batches.len() > 1) else {
+            return self.page_stream().map_ok(|page| futures::stream::iter(page.items.into_iter().map(Ok))).try_flatten().boxed()
+        };
+        let batch_streams = self.batch_streams();
+        let stream = self.with_scope_check(move || merge(
+            batch_streams().into_iter()
+                .map(|pages| pages.map_ok(|page| futures::stream::iter(page.items.into_iter().map(Ok))).try_flatten().boxed())
+                .collect(),
+            order,
+        ));
+        if let Some(mut filter) = self.filter.take() {
+            let dropped = Arc::clone(&self.dropped);
+            stream.filter(move |item| future::ready(match item {
+                Ok(item) if !filter(item) => {
+                    dropped.fetch_add(1, atomic::Ordering::Relaxed);
+                    false
+                }
+                Ok(_) | Err(_) => true,
+            })).boxed()
+        } else {
+            stream
+        }
+    }
+}
+
+impl<T: Keyed + DeserializeOwned + Send + 'static> Paginated<T> {
+    /// Skips items whose ID has already been returned by this stream.
+    ///
+    /// This is useful for endpoints whose results can change during pagination, like [`Stream::list`](crate::model::Stream::list), where items can move to a later page and would otherwise be returned twice. Note that the same effect can also cause items to be missed entirely. The number of skipped items is available from [`Paginated::dropped`] and [`Page::dropped`].
+    ///
+    /// The IDs of all returned items are kept in memory until the stream is dropped.
+    pub fn deduplicate(mut self) -> Paginated<T> {
+        let mut seen = HashSet::new();
+        self.filter = Some(Box::new(move |item: &T| seen.insert(item.key().clone())));
+        self
+    }
+}
+
+impl<T: DeserializeOwned + Send + 'static> Stream for Paginated<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<T, Error>>> {
+        if self.stream.is_none() {
+            self.stream = Some(self.item_stream());
+        }
+        self.stream.as_mut().expect("just inserted").poll_next_unpin(cx)
+    }
+}
+
+/// Requests pages from a separate task, up to `pages` pages ahead of the consumer.
+fn prefetch<T: Send + 'static>(mut stream: BoxStream<'static, Result<Page<T>, Error>>, pages: usize) -> BoxStream<'static, Result<Page<T>, Error>> {
+    let (tx, rx) = mpsc::channel(pages);
+    let task = tokio::spawn(async move {
+        while let Some(page) = stream.next().await {
+            let is_err = page.is_err();
+            if tx.send(page).await.is_err() || is_err { break }
+        }
+    });
+    // stop the task as soon as the consumer drops the stream, including any request in flight
+    let abort = AbortOnDrop(task.abort_handle());
+    futures::stream::unfold((rx, abort), |(mut rx, abort)| async move {
+        rx.recv().await.map(|page| (page, (rx, abort)))
+    }).boxed()
+}
+
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Merges streams which are each sorted according to `order` into a single sorted stream.
+fn merge<T: Send + 'static>(streams: Vec<BoxStream<'static, Result<T, Error>>>, order: fn(&T, &T) -> cmp::Ordering) -> BoxStream<'static, Result<T, Error>> {
+    let heads = streams.into_iter().map(|stream| (stream, None)).collect_vec();
+    futures::stream::try_unfold(heads, move |heads| async move {
+        // get the next item of each stream whose previous item has been returned
+        let heads = future::try_join_all(heads.into_iter().map(|(mut stream, head)| async move {
+            let head = if head.is_some() { head } else { stream.try_next().await? };
+            Ok::<_, Error>((stream, head))
+        })).await?;
+        // streams without a next item are exhausted
+        let mut heads = heads.into_iter().filter(|(_, head)| head.is_some()).collect_vec();
+        let Some((idx, _)) = heads.iter().enumerate().min_by(|(_, (_, a)), (_, (_, b))| order(a.as_ref().expect("filtered above"), b.as_ref().expect("filtered above"))) else { return Ok(None) };
+        let item = heads[idx].1.take().expect("filtered above");
+        Ok(Some((item, heads)))
+    }).boxed()
+}
+
+fn pages<T: DeserializeOwned>(client: Client, uri: String, query: Vec<(String, String)>, direction: Direction, start: Position, resilient: bool, semaphore: Option<Arc<Semaphore>>) -> impl Stream<Item = Result<Page<T>, Error>> {
+    futures::stream::try_unfold(start, move |position| {
+        let client = client.clone();
         let uri_clone = uri.clone();
         let query_clone = query.clone();
+        let semaphore = semaphore.clone();
         async move {
-            let query = if let Some(query) = cursor.query() {
+            let cursor = if let Position::At(ref cursor) = position { Some(cursor.clone()) } else { None };
+            let query = if let Some(query) = position.query(direction) {
                 query
             } else {
-                return Ok(None) // Cursor::End
+                return Ok(None) // Position::End
+            };
+            let params = query_clone.into_iter().chain(query).collect::<Vec<_>>();
+            let started = Instant::now();
+            let mut attempt = 0;
+            let PaginatedResult { data, pagination }: PaginatedResult<T> = loop {
+                attempt += 1;
+                let result = {
+                    // limits the number of concurrent requests across batches
+                    let _permit = if let Some(semaphore) = &semaphore { Some(semaphore.acquire().await.expect("semaphore is never closed")) } else { None };
+                    if resilient {
+                        // the retry policy is applied below instead
+                        client.get_raw_without_retries(&uri_clone, &params).await
+                    } else {
+                        client.get_raw(&uri_clone, &params).await
+                    }
+                };
+                match result {
+                    Ok(result) => break result,
+                    Err(e) => if !resilient {
+                        return Err(e)
+                    } else if let Some(delay) = client.inner.retry_policy.backoff(&Method::GET, &e, attempt, started) {
+                        // retry with the same cursor so no items are skipped
+                        sleep(delay).await;
+                    } else {
+                        return Err(Error::Pagination {
+                            source: Box::new(e),
+                            cursor,
+                        })
+                    },
+                }
             };
-            let params = query_clone.into_iter().chain(query);
-            let PaginatedResult { data, pagination }: PaginatedResult<T> = client.get_raw(&uri_clone, params).await?;
             if data.is_empty() {
                 Ok::<_, Error>(None)
             } else {
-                Ok(Some((futures::stream::iter(data.into_iter().map(Ok)), pagination.cursor)))
+                let cursor = if let Position::At(ref cursor) = pagination.cursor { Some(cursor.clone()) } else { None };
+                Ok(Some((Page { items: data, dropped: 0, cursor }, pagination.cursor)))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        std::collections::HashSet,
+        futures::{
+            StreamExt as _,
+            TryStreamExt as _,
+            executor::block_on,
+        },
+        crate::{
+            Client,
+            Credentials,
+            Error,
+        },
+        super::{
+            Paginated,
+            merge,
+        },
+    };
+
+    fn paginated() -> Paginated<()> {
+        let client = Client::new("twitch_helix tests", "test-client-id", Credentials::from_oauth_token("unused")).expect("failed to build client");
+        Paginated::new(&client, client.helix_url("/test"), Vec::default())
+    }
+
+    fn values(key: &str, batch: &[(String, String)]) -> Vec<String> {
+        batch.iter().filter(|(k, _)| k == key).map(|(_, v)| v.clone()).collect()
+    }
+
+    #[test]
+    fn batched_splits_values() {
+        let paginated = paginated().batched("id", (0..250).map(|i| i.to_string()), 100);
+        assert_eq!(paginated.batches.iter().map(Vec::len).collect::<Vec<_>>(), [100, 100, 50]);
+        let all = paginated.batches.iter().flat_map(|batch| values("id", batch)).collect::<HashSet<_>>();
+        assert_eq!(all, (0..250).map(|i| i.to_string()).collect());
+    }
+
+    #[test]
+    fn batched_without_values() {
+        let paginated = paginated().batched("id", Vec::default(), 100);
+        assert_eq!(paginated.batches, [Vec::default()]);
+    }
+
+    #[test]
+    fn batched_cross_product() {
+        let paginated = paginated()
+            .batched("game_id", (0..25).map(|i| format!("g{i}")), 10)
+            .batched("user_id", (0..150).map(|i| format!("u{i}")), 100);
+        assert_eq!(paginated.batches.len(), 3 * 2);
+        let mut pairs = HashSet::new();
+        for batch in &paginated.batches {
+            let games = values("game_id", batch);
+            let users = values("user_id", batch);
+            assert!(games.len() <= 10);
+            assert!(users.len() <= 100);
+            for game in &games {
+                for user in &users {
+                    assert!(pairs.insert((game.clone(), user.clone())), "pair requested twice");
+                }
             }
         }
-    }).try_flatten()
+        assert_eq!(pairs.len(), 25 * 150);
+    }
+
+    #[test]
+    fn merge_keeps_order() {
+        let streams = [vec![90, 70, 20], vec![100, 50, 40, 10], vec![], vec![60]].into_iter()
+            .map(|items| futures::stream::iter(items.into_iter().map(Ok)).boxed())
+            .collect();
+        let merged = block_on(merge(streams, |a: &u64, b: &u64| b.cmp(a)).try_collect::<Vec<_>>()).expect("merge failed");
+        assert_eq!(merged, [100, 90, 70, 60, 50, 40, 20, 10]);
+    }
+
+    #[test]
+    fn merge_returns_errors() {
+        let streams = vec![
+            futures::stream::iter([Ok(3), Ok(1)]).boxed(),
+            futures::stream::iter([Ok(2), Err(Error::ExactlyOne(true))]).boxed(),
+        ];
+        let merged = block_on(merge(streams, |a: &u64, b: &u64| b.cmp(a)).collect::<Vec<_>>());
+        assert!(matches!(merged[..], [Ok(3), Ok(2), Err(Error::ExactlyOne(true))]));
+    }
 }
@@ -0,0 +1,98 @@
+//! Persistence for OAuth tokens, see [`Credentials::with_token_store`](crate::Credentials::with_token_store)
+
+use {
+    std::{
+        io,
+        path::PathBuf,
+        process,
+    },
+    async_trait::async_trait,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    tokio::{
+        fs,
+        io::AsyncWriteExt as _,
+    },
+    crate::Error,
+};
+
+/// An OAuth token as saved in a [`TokenStore`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StoredToken {
+    /// The access token used to authenticate requests.
+    pub access_token: String,
+    /// The refresh token used to get a new access token. `None` for app access tokens.
+    pub refresh_token: Option<String>,
+}
+
+/// A place where OAuth tokens are persisted, so they survive restarts of the process and can be shared between instances.
+///
+/// A store is only ever used for a single set of [`Credentials`](crate::Credentials). Errors should be wrapped in [`Error::TokenStore`].
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    /// Returns the token that was saved last, if any. Called before the credentials are first used, and before a user access token is refreshed after being rejected.
+    async fn load(&self) -> Result<Option<StoredToken>, Error>;
+
+    /// Saves a token. Called whenever a new token is obtained, e.g. because the old one expired.
+    async fn save(&self, token: &StoredToken) -> Result<(), Error>;
+
+    /// Removes the saved token. Called when the token is [revoked](crate::Client::revoke_token).
+    async fn clear(&self) -> Result<(), Error>;
+}
+
+/// A [`TokenStore`] which saves the token in a JSON file.
+///
+/// On Unix, the file is created with permissions that only allow the owner to read it.
+pub struct JsonFileTokenStore {
+    path: PathBuf,
+}
+
+impl JsonFileTokenStore {
+    /// Uses the file at the given path. The file doesn't have to exist yet.
+    pub fn new(path: impl Into<PathBuf>) -> JsonFileTokenStore {
+        JsonFileTokenStore { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl TokenStore for JsonFileTokenStore {
+    async fn load(&self) -> Result<Option<StoredToken>, Error> {
+        match fs::read(&self.path).await {
+            Ok(buf) => Ok(Some(serde_json::from_slice(&buf).map_err(|e| Error::TokenStore(Box::new(e)))?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::TokenStore(Box::new(e))),
+        }
+    }
+
+    async fn save(&self, token: &StoredToken) -> Result<(), Error> {
+        let buf = serde_json::to_vec_pretty(token).map_err(|e| Error::TokenStore(Box::new(e)))?;
+        // write to a temporary file first so a crash doesn't leave a truncated file behind, using a unique name so concurrent saves by multiple instances don't interfere
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(format!(".{}.{:016x}.tmp", process::id(), rand::random::<u64>()));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // the file contains secrets, so only the owner may read it
+        #[cfg(unix)] options.mode(0o600);
+        let write = async {
+            let mut file = options.open(&tmp_path).await?;
+            file.write_all(&buf).await?;
+            file.sync_all().await?;
+            fs::rename(&tmp_path, &self.path).await
+        };
+        if let Err(e) = write.await {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(Error::TokenStore(Box::new(e)))
+        }
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<(), Error> {
+        match fs::remove_file(&self.path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::TokenStore(Box::new(e))),
+        }
+    }
+}